@@ -11,20 +11,278 @@
 //! which should be asymptotically optimal as the product of the `Arbor`
 //! is an ordered representation of its contents.
 
+use std::hash::Hash;
+use std::io::{self, Write};
+use std::ops::{Deref, Range};
+use std::sync::Arc;
+use std::sync::mpsc::{self, Receiver, SyncSender, TryRecvError};
+
 use TrieStorage;
 use TrieRef;
 use CursorMerger;
+use trie::{Cursor, Retract, Violation, fueled_step};
+use consolidate::{Consolidate, Consolidated};
+use flatten::{Flatten, Tuples};
+use lattice::Lattice;
+use time::Advance;
+use persist::{self, Persist};
+use policy::{MergePolicy, Cascade};
+use bloom::{self, Bloom, Density};
+use radix::Radix;
+
+// Multiples of an appended trie's tuple count, in tuples, of merge work to perform.
+const MERGE_FUEL: usize = 2;
 
-#[derive(Debug, PartialEq, Eq)]
-pub struct Arbor<T: TrieStorage> {
-	tries: Vec<T>,
+#[derive(Debug)]
+pub struct Arbor<T: TrieStorage, P: MergePolicy=Cascade> {
+	layers: Vec<Layer<T>>,
+	// for each layer, the sorted top-level keys removed from it but not yet dropped.
+	masks: Vec<Vec<T::Key>>,
+	// for each layer, a filter of the top-level keys of its tries, if filters are enabled.
+	filters: Vec<Option<Arc<Bloom>>>,
+	filtering: Option<Filtering<T::Key>>,
+	policy: P,
+	worker: Option<Worker<T>>,
 }
 
-impl<T: TrieStorage> Arbor<T> {
+// How to build filters of top-level keys: their density, and how to hash keys.
+//
+// the hash is captured when filters are enabled, where keys are known to be hashable.
+#[derive(Debug)]
+struct Filtering<K> {
+	density: Density,
+	hash: fn(&K) -> u64,
+}
+
+impl<K> Filtering<K> {
+	// a filter of the keys of `ranges`.
+	fn build<T, R>(&self, ranges: &[(R, usize, usize)]) -> Bloom where T: TrieStorage<Key=K>, R: Deref<Target=T> {
+		let mut filter = Bloom::new(ranges.iter().map(|x| x.2 - x.1).sum(), self.density);
+		for &(ref trie, lower, upper) in ranges {
+			for index in lower .. upper {
+				filter.insert((self.hash)(trie.key(index)));
+			}
+		}
+		filter
+	}
+}
+
+// A trie managed by the arbor, or a run of tries in the process of being merged, 
+// either progressively by the arbor or by a background worker.
+#[derive(Debug)]
+enum Layer<T: TrieStorage> {
+	Complete(Arc<T>),
+	Merging(Merge<T>),
+	Background(Pending<T>),
+}
+
+impl<T: TrieStorage> Layer<T> {
+	// the number of tuples the layer represents, before any cancellation.
+	fn tuples(&self) -> usize {
+		match *self {
+			Layer::Complete(ref trie) => trie.tuples(),
+			Layer::Merging(ref merge) => distinct(&merge.inputs[..]).iter().map(|x| x.tuples()).sum(),
+			Layer::Background(ref pending) => distinct(&pending.inputs[..]).iter().map(|x| x.tuples()).sum(),
+		}
+	}
+	fn is_complete(&self) -> bool {
+		match *self {
+			Layer::Complete(_) => true,
+			Layer::Merging(_) => false,
+			Layer::Background(_) => false,
+		}
+	}
+	// extracts the trie, finishing any in-progress merge.
+	fn complete(self) -> Arc<T> {
+		match self {
+			Layer::Complete(trie) => trie,
+			Layer::Merging(mut merge) => {
+				let mut fuel = usize::MAX;
+				merge.work(&mut fuel);
+				Arc::new(merge.result)
+			},
+			Layer::Background(pending) => {
+				Arc::new(pending.result.recv().expect("background merge failed"))
+			},
+		}
+	}
+}
+
+// A merge in progress of ranges of tries, with `result` holding the merge of the keys of 
+// each range before its entry in `lowers`.
+#[derive(Debug)]
+struct Merge<T: TrieStorage> {
+	inputs: Vec<(Arc<T>, usize, usize)>,
+	lowers: Vec<usize>,
+	result: T,
+}
+
+impl<T: TrieStorage> Merge<T> {
+	fn new(inputs: Vec<(Arc<T>, usize, usize)>) -> Merge<T> {
+		Merge {
+			result: if inputs.len() == 2 { T::with_capacity(&inputs[0].0, &inputs[1].0) } else { T::new() },
+			lowers: inputs.iter().map(|x| x.1).collect(),
+			inputs: inputs,
+		}
+	}
+
+	// performs about `fuel` tuples of merging, and reports whether the merge is complete.
+	fn work(&mut self, fuel: &mut usize) -> bool {
+		if self.inputs.len() == 2 {
+			let (trie1, upper1): (&T, usize) = (&self.inputs[0].0, self.inputs[0].2);
+			let (trie2, upper2): (&T, usize) = (&self.inputs[1].0, self.inputs[1].2);
+			let (lowers1, lowers2) = self.lowers.split_at_mut(1);
+			let (lower1, lower2) = (&mut lowers1[0], &mut lowers2[0]);
+			self.result.extend_merge_fueled((trie1, lower1, upper1), (trie2, lower2, upper2), fuel);
+			if *lower1 == upper1 && *lower2 < upper2 && *fuel > 0 {
+				let (step, tuples) = fueled_step(trie2, *lower2, upper2 - *lower2, *fuel);
+				self.result.extend_trie(trie2, *lower2, *lower2 + step);
+				*lower2 += step;
+				*fuel = fuel.saturating_sub(tuples);
+			}
+			if *lower2 == upper2 && *lower1 < upper1 && *fuel > 0 {
+				let (step, tuples) = fueled_step(trie1, *lower1, upper1 - *lower1, *fuel);
+				self.result.extend_trie(trie1, *lower1, *lower1 + step);
+				*lower1 += step;
+				*fuel = fuel.saturating_sub(tuples);
+			}
+		}
+		else {
+			// merges the keys of all inputs less than a bound, chosen so that each input
+			// yields at most its `share` of tuples (or one key), until the fuel or the
+			// inputs are exhausted.
+			while *fuel > 0 && !self.is_complete() {
+				let share = ::std::cmp::max(1, *fuel / self.inputs.len());
+				let uppers: Vec<usize> = {
+					let mut bound = None;
+					for (&(ref trie, _, upper), &lower) in self.inputs.iter().zip(self.lowers.iter()) {
+						if lower == upper { continue; }
+						let step = fueled_step(&**trie, lower, upper - lower, share).0;
+						if lower + step < upper {
+							let key = trie.key(lower + step);
+							if bound.map(|bound| key < bound).unwrap_or(true) {
+								bound = Some(key);
+							}
+						}
+					}
+					self.inputs.iter().zip(self.lowers.iter()).map(|(&(ref trie, _, upper), &lower)| {
+						match bound {
+							Some(key) => trie.lower_bound(key, lower, upper),
+							None => upper,
+						}
+					}).collect()
+				};
+				{
+					let ranges: Vec<_> = self.inputs.iter().zip(self.lowers.iter()).zip(uppers.iter()).map(|((input, &lower), &upper)| (&*input.0, lower, upper)).collect();
+					self.result.extend_merge_many(&ranges[..]);
+				}
+				for ((input, lower), upper) in self.inputs.iter().zip(self.lowers.iter_mut()).zip(uppers) {
+					*fuel = fuel.saturating_sub(input.0.tuples_in(*lower, upper));
+					*lower = upper;
+				}
+			}
+		}
+		self.is_complete()
+	}
+
+	fn is_complete(&self) -> bool {
+		self.inputs.iter().zip(self.lowers.iter()).all(|(input, &lower)| lower == input.2)
+	}
+}
+
+// A merge of ranges of tries handed to a background worker, whose result arrives on `result`.
+#[derive(Debug)]
+struct Pending<T: TrieStorage> {
+	inputs: Vec<(Arc<T>, usize, usize)>,
+	result: Receiver<T>,
+}
 
-	/// Allocates a new empty arbor.
+// Ranges of tries to merge, and where to send the result.
+type Job<T> = (Vec<(Arc<T>, usize, usize)>, mpsc::Sender<T>);
+
+// A thread merging tries handed to it, at most `capacity` of whose merges may be queued.
+#[derive(Debug)]
+struct Worker<T: TrieStorage> {
+	jobs: SyncSender<Job<T>>,
+	capacity: usize,
+}
+
+impl<T: TrieStorage+Send+Sync+'static> Worker<T> {
+	fn spawn(capacity: usize) -> Worker<T> {
+		let (jobs, queue) = mpsc::sync_channel::<Job<T>>(capacity);
+		::std::thread::spawn(move || {
+			// the worker exits once its arbor, and so the sending half of the queue, is dropped.
+			for (inputs, result) in queue {
+				let ranges: Vec<(&T, usize, usize)> = inputs.iter().map(|&(ref trie, lower, upper)| (&**trie, lower, upper)).collect();
+				let merged = if ranges.len() == 2 {
+					let mut merged = T::with_capacity(ranges[0].0, ranges[1].0);
+					merged.extend_merge(ranges[0], ranges[1]);
+					merged
+				}
+				else {
+					let mut merged = T::new();
+					merged.extend_merge_many(&ranges[..]);
+					merged
+				};
+				// the arbor may have dropped the merge, for example when consolidating.
+				let _ = result.send(merged);
+			}
+		});
+		Worker { jobs: jobs, capacity: capacity }
+	}
+}
+
+// the tries of `inputs` once each, as the ranges of each trie are listed together.
+fn distinct<T: TrieStorage>(inputs: &[(Arc<T>, usize, usize)]) -> Vec<&T> {
+	let mut tries = Vec::with_capacity(inputs.len());
+	for (index, input) in inputs.iter().enumerate() {
+		if index == 0 || !Arc::ptr_eq(&inputs[index - 1].0, &input.0) {
+			tries.push(&*input.0);
+		}
+	}
+	tries
+}
+
+// pushes the ranges of `trie` within `lower .. upper` that avoid the keys of `mask`, if non-empty.
+fn push_unmasked<T, R>(trie: R, lower: usize, upper: usize, mask: &[T::Key], ranges: &mut Vec<(R, usize, usize)>)
+where T: TrieStorage, R: Deref<Target=T>+Clone {
+	let mut lower = lower;
+	for key in mask {
+		let index = trie.lower_bound(key, lower, upper);
+		if index < upper && trie.key(index) == key {
+			if lower < index {
+				ranges.push((trie.clone(), lower, index));
+			}
+			lower = index + 1;
+		}
+	}
+	if lower < upper {
+		ranges.push((trie, lower, upper));
+	}
+}
+
+// arbors are compared by the ranges of tries they present, rather than by how far their
+// merges have progressed or how they filter keys and schedule merges.
+impl<T: TrieStorage+PartialEq, P: MergePolicy> PartialEq for Arbor<T, P> {
+	fn eq(&self, other: &Arbor<T, P>) -> bool {
+		self.ranges() == other.ranges()
+	}
+}
+
+impl<T: TrieStorage+Eq, P: MergePolicy> Eq for Arbor<T, P> { }
+
+impl<T: TrieStorage> Arbor<T> {
+	/// Allocates a new empty arbor, merging tries by the default `Cascade` policy.
 	pub fn new() -> Arbor<T> {
-		Arbor { tries: vec![] }
+		Arbor::with_policy(Cascade::default())
+	}
+}
+
+impl<T: TrieStorage, P: MergePolicy> Arbor<T, P> {
+
+	/// Allocates a new empty arbor, merging tries as selected by `policy`.
+	pub fn with_policy(policy: P) -> Arbor<T, P> {
+		Arbor { layers: vec![], masks: vec![], filters: vec![], filtering: None, policy: policy, worker: None }
 	}
 
 	/// Reports the number of tuples across all managed tries.
@@ -33,8 +291,8 @@ impl<T: TrieStorage> Arbor<T> {
 	/// enumerated by `cursor`, which has the opportunity to merge like elements.
 	pub fn size(&self) -> usize {
 		let mut count = 0;
-		for trie in &self.tries {
-			count += trie.tuples();
+		for layer in &self.layers {
+			count += layer.tuples();
 		}
 		count
 	}
@@ -50,12 +308,26 @@ impl<T: TrieStorage> Arbor<T> {
 
 	/// Adds an ordered sequence of tuples to the collection.
 	///
-	/// If the tuples aren't in order, something horrible probably happens.
-	/// Nothing memory unsafe, but ... why would you do this?
+	/// The tuples must be in order, and out of order tuples are rejected in debug 
+	/// builds. Tuples in any other order can be added with `extend_unordered`.
 	pub fn extend_ordered<I: Iterator<Item=T::Item>>(&mut self, iterator: I) {
 		self.append(T::from_ordered(iterator));
 	}
 
+	/// Adds a sequence of tuples, in any order, to the collection.
+	///
+	/// The tuples are sorted and consolidated by `TrieStorage::from_unordered`.
+	pub fn extend_unordered<I: Iterator<Item=T::Item>>(&mut self, iterator: I) {
+		self.append(T::from_unordered(iterator));
+	}
+
+	/// Adds a sequence of tuples with integer keys, in any order, to the collection.
+	///
+	/// The tuples are sorted and consolidated by `TrieStorage::from_unordered_radix`.
+	pub fn extend_unordered_radix<I: Iterator<Item=T::Item>>(&mut self, iterator: I) where T::Key: Radix {
+		self.append(T::from_unordered_radix(iterator));
+	}
+
 	/// Adds an entire trie into the collection.
 	///
 	/// This method can be helpful if the resources required for the trie
@@ -63,43 +335,908 @@ impl<T: TrieStorage> Arbor<T> {
 	/// in `extend_ordered`. The method can be quite fast in this case, as
 	/// it does not need to re-process every tuple in the input batch.
 	///
-	/// The method will perform merging of tries if the introduced trie has
-	/// a size within a factor of two of the smallest trie the arbor currently
-	/// manages. This can be quite *not fast*, but it should be improved with
-	/// progressive merging.
+	/// Merges are performed progressively: each in-progress merge advances 
+	/// by an amount of work proportional to the number of appended tuples, 
+	/// measured in the tuples the merge consumes. The values of a top-level key
+	/// found in several tries are merged at once, so a step of a merge may exceed
+	/// its share of work by the tuples of one key. A merge of a run of tries starts
+	/// when the arbor's policy selects it, and waits while any of them is being merged.
+	///
+	/// With background merges enabled, merges are instead handed to a worker
+	/// thread, and those it has finished are swapped in. The method blocks only
+	/// while the worker's queue of merges is full.
 	pub fn append(&mut self, trie: T) {
 
-		// This method could be optimized to search out an empty location where
-		// the trie can be inserted. It presently accumulates up any small tries
-		// as it goes, which ensures the sizing invariant but also performs work
-		// it may not have needed to do just yet.
+		// swap in the results of finished background merges.
+		for layer in &mut self.layers {
+			let result = match *layer {
+				Layer::Background(ref pending) => {
+					match pending.result.try_recv() {
+						Ok(trie) => Some(trie),
+						Err(TryRecvError::Empty) => None,
+						Err(TryRecvError::Disconnected) => panic!("background merge failed"),
+					}
+				},
+				_ => None,
+			};
+			if let Some(trie) = result {
+				*layer = Layer::Complete(Arc::new(trie));
+			}
+		}
 
-		self.tries.push(trie);
-		while self.tries.len() > 1 {
+		// advance each in-progress merge, in proportion to the appended tuples.
+		let fuel = MERGE_FUEL * trie.tuples();
+		for layer in &mut self.layers {
+			let complete = match *layer {
+				Layer::Merging(ref mut merge) => merge.work(&mut fuel.clone()),
+				Layer::Complete(_) => false,
+				Layer::Background(_) => false,
+			};
+			if complete {
+				let trie = ::std::mem::replace(layer, Layer::Complete(Arc::new(T::new()))).complete();
+				*layer = Layer::Complete(trie);
+			}
+		}
+
+		let filter = self.filter(&[(&trie, 0, trie.keys())]);
+		self.layers.push(Layer::Complete(Arc::new(trie)));
+		self.masks.push(Vec::new());
+		self.filters.push(filter);
+		self.start_merges();
+	}
 
-			// acquire the last two elements
-			let trie1 = self.tries.pop().unwrap();
-			let trie2 = self.tries.pop().unwrap();
+	/// Retracts a single tuple, by adding it with its difference negated.
+	///
+	/// The retraction cancels the tuple once the tries holding them are merged, and 
+	/// `consolidated` cursors cancel them immediately.
+	pub fn retract(&mut self, mut tuple: T::Item) where T: Retract {
+		T::negate_item(&mut tuple);
+		self.push(tuple);
+	}
 
-			// if trie1 is within 2x of trie2 merge, ...
-			if trie1.tuples() > trie2.tuples() / 2 {
-				let mut result = T::with_capacity(&trie1, &trie2);
-				result.extend_merge((&trie1, 0, trie1.keys()), (&trie2, 0, trie2.keys()));
-				self.tries.push(result);
+	/// Removes all tuples with the top-level key `key`.
+	///
+	/// The tuples are masked immediately, and physically dropped when the tries holding 
+	/// them are next merged. Tuples with the key added afterwards are unaffected.
+	pub fn remove_key(&mut self, key: &T::Key) where T::Key: Clone {
+		for index in 0 .. self.layers.len() {
+			let present = {
+				let mut ranges = Vec::new();
+				self.layer_ranges(index, &mut ranges);
+				ranges.iter().any(|&(trie, lower, upper)| {
+					let index = trie.lower_bound(key, lower, upper);
+					index < upper && trie.key(index) == key
+				})
+			};
+			if present {
+				if let Err(position) = self.masks[index].binary_search(key) {
+					self.masks[index].insert(position, key.clone());
+				}
 			}
-			// ... otherwise push them back and return.
-			else {
-				self.tries.push(trie2);
-				self.tries.push(trie1);
-				return;
+		}
+	}
+
+	/// Completes all merges, including those handed to a background worker.
+	///
+	/// Merges that completed merges lead the policy to select are also completed, so 
+	/// that afterwards the arbor holds only complete tries, none of which the policy
+	/// would merge.
+	pub fn wait_for_merges(&mut self) {
+		while self.layers.iter().any(|layer| !layer.is_complete()) {
+			for layer in &mut self.layers {
+				if !layer.is_complete() {
+					let trie = ::std::mem::replace(layer, Layer::Complete(Arc::new(T::new()))).complete();
+					*layer = Layer::Complete(trie);
+				}
+			}
+			self.start_merges();
+		}
+	}
+
+	// starts merging runs of complete tries selected by the policy, either progressively
+	// or in the background. a run involving an in-progress merge waits until it completes.
+	fn start_merges(&mut self) {
+		loop {
+			let sizes: Vec<usize> = self.layers.iter().map(|layer| layer.tuples()).collect();
+			match self.policy.select(&sizes[..]) {
+				Some(run) => {
+					assert!(run.start + 1 < run.end && run.end <= sizes.len(), "invalid merge run: {:?}", run);
+					if !self.layers[run.clone()].iter().all(|layer| layer.is_complete()) {
+						break;
+					}
+					let tries: Vec<_> = self.layers.drain(run.clone()).map(|layer| layer.complete()).collect();
+					let masks: Vec<_> = self.masks.drain(run.clone()).collect();
+					self.filters.drain(run.clone());
+
+					// masked keys are left out of the merge, and so dropped.
+					let mut inputs = Vec::with_capacity(tries.len());
+					for (trie, mask) in tries.into_iter().zip(masks.iter()) {
+						let upper = trie.keys();
+						push_unmasked(trie, 0, upper, &mask[..], &mut inputs);
+					}
+					let filter = self.filter(&inputs[..]);
+
+					let layer = match self.worker {
+						Some(ref worker) => {
+							let (sender, result) = mpsc::channel();
+							worker.jobs.send((inputs.clone(), sender)).expect("background merge worker failed");
+							Layer::Background(Pending { inputs: inputs, result: result })
+						},
+						None => Layer::Merging(Merge::new(inputs)),
+					};
+					self.layers.insert(run.start, layer);
+					self.masks.insert(run.start, Vec::new());
+					self.filters.insert(run.start, filter);
+				},
+				None => break,
+			}
+		}
+	}
+
+	/// Merges all managed tries, including those in the process of being merged, into one.
+	///
+	/// The tries are merged in a single pass, rather than as a sequence of pairwise merges.
+	pub fn consolidate(&mut self) {
+		let trie = {
+			let ranges = self.ranges();
+			let mut trie = T::new();
+			trie.extend_merge_many(&ranges[..]);
+			trie
+		};
+		self.layers.clear();
+		self.masks.clear();
+		self.filters.clear();
+		if trie.tuples() > 0 {
+			let filter = self.filter(&[(&trie, 0, trie.keys())]);
+			self.layers.push(Layer::Complete(Arc::new(trie)));
+			self.masks.push(Vec::new());
+			self.filters.push(filter);
+		}
+	}
+
+	/// Advances the times of all tuples by `frontier`, consolidating tuples that then collide.
+	///
+	/// Any in-progress merges are completed first. Tuples are consolidated within each 
+	/// trie, and tuples in different tries are consolidated as the tries are merged.
+	/// Removed keys are dropped as each trie is advanced.
+	pub fn advance_by<Time: Lattice>(&mut self, frontier: &[Time]) where T: Advance<Time> {
+		for (layer, mask) in self.layers.iter_mut().zip(self.masks.iter()) {
+			let trie = ::std::mem::replace(layer, Layer::Complete(Arc::new(T::new()))).complete();
+			let mut ranges = Vec::new();
+			push_unmasked(&*trie, 0, trie.keys(), &mask[..], &mut ranges);
+			let mut advanced = T::new();
+			for (trie, lower, upper) in ranges {
+				advanced.extend_advanced(trie, lower, upper, frontier);
+			}
+			*layer = Layer::Complete(Arc::new(advanced));
+		}
+		self.layers.retain(|layer| layer.tuples() > 0);
+		self.masks = self.layers.iter().map(|_| Vec::new()).collect();
+		self.refilter();
+	}
+
+	/// Checks the invariants of each managed trie, and reports the first violation found.
+	///
+	/// A violation is reported with the position of its trie, counting from the oldest,
+	/// where an in-progress merge counts as its merged prefix followed by its inputs, and
+	/// a merge in the background counts as its inputs.
+	pub fn validate(&self) -> Result<(), (usize, Violation)> {
+		let mut tries = Vec::with_capacity(self.layers.len());
+		for layer in &self.layers {
+			match *layer {
+				Layer::Complete(ref trie) => tries.push(&**trie),
+				Layer::Merging(ref merge) => {
+					tries.push(&merge.result);
+					tries.extend(distinct(&merge.inputs[..]));
+				},
+				Layer::Background(ref pending) => {
+					tries.extend(distinct(&pending.inputs[..]));
+				},
 			}
 		}
+		for (index, trie) in tries.into_iter().enumerate() {
+			trie.validate().map_err(|violation| (index, violation))?;
+		}
+		Ok(())
+	}
+
+	// a filter of the keys of `ranges`, if filters are enabled.
+	fn filter<R: Deref<Target=T>>(&self, ranges: &[(R, usize, usize)]) -> Option<Arc<Bloom>> {
+		self.filtering.as_ref().map(|filtering| Arc::new(filtering.build(ranges)))
+	}
+
+	// the hash the filters are built with, if filters are enabled.
+	fn hash(&self) -> Option<fn(&T::Key) -> u64> {
+		self.filtering.as_ref().map(|filtering| filtering.hash)
+	}
+
+	// rebuilds the filter of each layer, or discards them if filters are not enabled.
+	fn refilter(&mut self) {
+		let filters = (0 .. self.layers.len()).map(|index| {
+			let mut ranges = Vec::new();
+			self.layer_ranges(index, &mut ranges);
+			self.filter(&ranges[..])
+		}).collect();
+		self.filters = filters;
+	}
+
+	// the non-empty ranges of tries whose merged contents are the contents of the arbor.
+	fn ranges(&self) -> Vec<(&T, usize, usize)> {
+		let mut ranges = Vec::with_capacity(self.layers.len());
+		for index in 0 .. self.layers.len() {
+			self.layer_ranges(index, &mut ranges);
+		}
+		ranges
+	}
+
+	// the non-empty ranges of tries, as `ranges`, each with the filter of its layer if any.
+	fn probes<'a>(&'a self) -> Vec<Probe<'a, T>> {
+		let mut probes = Vec::with_capacity(self.layers.len());
+		let mut ranges = Vec::new();
+		for index in 0 .. self.layers.len() {
+			self.layer_ranges(index, &mut ranges);
+			let filter = self.filters[index].as_deref();
+			probes.extend(ranges.drain(..).map(|(trie, lower, upper)| (trie, lower, upper, filter)));
+		}
+		probes
+	}
+
+	// pushes the non-empty ranges of the layer at `index`, avoiding its removed keys.
+	fn layer_ranges<'a>(&'a self, index: usize, ranges: &mut Vec<(&'a T, usize, usize)>) {
+		let mask = &self.masks[index][..];
+		match self.layers[index] {
+			Layer::Complete(ref trie) => push_unmasked(&**trie, 0, trie.keys(), mask, ranges),
+			Layer::Merging(ref merge) => {
+				push_unmasked(&merge.result, 0, merge.result.keys(), mask, ranges);
+				for (&(ref trie, _, upper), &lower) in merge.inputs.iter().zip(merge.lowers.iter()) {
+					push_unmasked(&**trie, lower, upper, mask, ranges);
+				}
+			},
+			Layer::Background(ref pending) => {
+				for &(ref trie, lower, upper) in &pending.inputs {
+					push_unmasked(&**trie, lower, upper, mask, ranges);
+				}
+			},
+		}
 	}
 }
 
-impl<T: TrieStorage> Arbor<T> {
+impl<T: TrieStorage+Send+Sync+'static, P: MergePolicy> Arbor<T, P> {
+	/// Hands merges started from now on to a background thread, rather than performing 
+	/// them progressively in `append`.
+	///
+	/// The tries being merged remain shared with the arbor, so that cursors present their
+	/// contents until the merged trie is swapped in by a later `append`. At most `capacity` 
+	/// merges are queued for the thread, beyond which `append` blocks until one starts.
+	pub fn enable_background_merges(&mut self, capacity: usize) {
+		if self.worker.as_ref().map(|worker| worker.capacity != capacity).unwrap_or(true) {
+			self.worker = Some(Worker::spawn(capacity));
+		}
+	}
+}
+
+impl<T: TrieStorage, P: MergePolicy> Arbor<T, P> where T::Key: Hash {
+	/// Keeps a Bloom filter of the top-level keys of each trie, which `get` and `get_many`
+	/// consult to skip tries that cannot contain a key, as do those of snapshots.
+	///
+	/// Each filter mistakes about a fraction `rate` of absent keys for present ones, and
+	/// takes a little more than `-ln(rate) / ln(2)^2` bits for each key; a rate of 0.01
+	/// takes ten bits, and a rate of 0.001 sixteen.
+	/// Filters are built as tries are appended and as merges start. Their density is
+	/// written by `write_to`, and reading the arbor builds them again.
+	pub fn enable_filters(&mut self, rate: f64) {
+		self.filtering = Some(Filtering { density: Density::for_rate(rate), hash: bloom::hash::<T::Key> });
+		self.refilter();
+	}
+}
+
+impl<T: TrieStorage, P: MergePolicy> Arbor<T, P> {
 	/// Provides a cursor for traversing the arbor's contents.
+	///
+	/// Tries that are in the process of being merged are presented as the merged 
+	/// prefix of their contents, and the still unmerged remainders of each input.
+	pub fn cursor<'a>(&'a self) -> CursorMerger<'a, <T as TrieRef<'a>>::Cursor> where T : TrieRef<'a> {
+		CursorMerger::from(self.ranges().into_iter().map(|(trie, lower, upper)| trie.cursor(lower, upper)))
+	}
+
+	/// Provides an iterator over the arbor's tuples, flattened and in order.
+	///
+	/// Tuples present in several tries are presented once for each, as with `cursor`.
+	pub fn tuples<'a>(&'a self) -> Tuples<'a, CursorMerger<'a, <T as TrieRef<'a>>::Cursor>> where T : TrieRef<'a>, <T as TrieRef<'a>>::Cursor: Flatten<'a> {
+		Tuples::new(self.cursor())
+	}
+
+	/// Provides a cursor for traversing the arbor's contents with like tuples consolidated.
+	///
+	/// Differences for the same tuple in different tries are added together, and tuples 
+	/// whose net difference is zero are omitted, as are keys with no remaining tuples.
+	pub fn consolidated<'a>(&'a self) -> Consolidated<'a, <T as TrieRef<'a>>::Cursor> where T : TrieRef<'a>, <T as TrieRef<'a>>::Cursor: Consolidate<'a> {
+		Consolidated::new(self.cursor())
+	}
+
+	/// Provides a cursor over the values associated with `key`, merged across all tries.
+	///
+	/// Each trie is searched once for the key, and the merger is empty if no trie has it.
+	/// With filters enabled, tries whose filters exclude the key are not searched.
+	pub fn get<'a>(&'a self, key: &T::Key) -> CursorMerger<'a, <<T as TrieRef<'a>>::Cursor as Cursor<'a>>::Val> 
+	where T: TrieRef<'a>, <<T as TrieRef<'a>>::Cursor as Cursor<'a>>::Val: Cursor<'a> {
+		get_from(self.probes(), self.hash(), key)
+	}
+
+	/// Provides cursors over the values associated with each of `keys`, as `get` would.
+	///
+	/// The keys must be sorted, and are found with a single forward sweep through each
+	/// trie, each search starting from where the previous one stopped. The cursors are
+	/// returned in the order of `keys`. With filters enabled, the sweep through a trie 
+	/// skips the keys its filter excludes.
+	pub fn get_many<'a>(&'a self, keys: &[T::Key]) -> Vec<CursorMerger<'a, <<T as TrieRef<'a>>::Cursor as Cursor<'a>>::Val>> 
+	where T: TrieRef<'a>, <<T as TrieRef<'a>>::Cursor as Cursor<'a>>::Val: Cursor<'a> {
+		get_many_from(self.probes(), self.hash(), keys)
+	}
+
+	/// Provides a cursor for traversing the keys in `range`, and their values.
+	///
+	/// Each trie is searched once for each end of the range, and the cursor stops at the
+	/// upper bound without examining later keys.
+	pub fn range<'a>(&'a self, range: Range<T::Key>) -> CursorMerger<'a, <T as TrieRef<'a>>::Cursor> where T : TrieRef<'a> {
+		range_from(self.ranges(), range)
+	}
+
+	/// Captures the arbor's current tries as an immutable snapshot.
+	///
+	/// The snapshot shares the arbor's tries rather than copying them, and may be sent to
+	/// and queried from other threads while the arbor accepts further appends and merges.
+	/// Tries in the process of being merged are captured as the inputs to their merge.
+	pub fn snapshot(&self) -> Snapshot<T> {
+		let mut tries = Vec::with_capacity(self.layers.len());
+		let mut filters = Vec::with_capacity(self.layers.len());
+		for ((layer, mask), filter) in self.layers.iter().zip(self.masks.iter()).zip(self.filters.iter()) {
+			match *layer {
+				Layer::Complete(ref trie) => push_unmasked(trie.clone(), 0, trie.keys(), &mask[..], &mut tries),
+				Layer::Merging(ref merge) => {
+					for &(ref trie, lower, upper) in &merge.inputs {
+						push_unmasked(trie.clone(), lower, upper, &mask[..], &mut tries);
+					}
+				},
+				Layer::Background(ref pending) => {
+					for &(ref trie, lower, upper) in &pending.inputs {
+						push_unmasked(trie.clone(), lower, upper, &mask[..], &mut tries);
+					}
+				},
+			}
+			// each range of the layer shares its filter.
+			filters.resize(tries.len(), filter.clone());
+		}
+		Snapshot { tries: tries, filters: filters, hash: self.hash() }
+	}
+}
+
+/// An immutable snapshot of the tries of an `Arbor`.
+///
+/// A snapshot is reference counted, and cloning it or capturing it from an arbor copies
+/// no tuples. It holds on to the tries it captured, even as the arbor merges them away,
+/// and to the filters of their layers, which its lookups consult as the arbor's do.
+#[derive(Debug)]
+pub struct Snapshot<T: TrieStorage> {
+	tries: Vec<(Arc<T>, usize, usize)>,
+	// for each range of a trie, the filter of its layer, if filters were enabled.
+	filters: Vec<Option<Arc<Bloom>>>,
+	hash: Option<fn(&T::Key) -> u64>,
+}
+
+impl<T: TrieStorage> Clone for Snapshot<T> {
+	fn clone(&self) -> Snapshot<T> {
+		Snapshot { tries: self.tries.clone(), filters: self.filters.clone(), hash: self.hash }
+	}
+}
+
+impl<T: TrieStorage> Snapshot<T> {
+	/// Reports the number of tuples across all captured tries, as `Arbor::size`.
+	pub fn size(&self) -> usize {
+		distinct(&self.tries[..]).iter().map(|trie| trie.tuples()).sum()
+	}
+
+	/// Provides a cursor for traversing the snapshot's contents.
 	pub fn cursor<'a>(&'a self) -> CursorMerger<'a, <T as TrieRef<'a>>::Cursor> where T : TrieRef<'a> {
-		CursorMerger::from(self.tries.iter().map(|x| x.cursor(0, x.keys_cnt())))
+		CursorMerger::from(self.ranges().into_iter().map(|(trie, lower, upper)| trie.cursor(lower, upper)))
+	}
+
+	/// Provides an iterator over the snapshot's tuples, flattened and in order.
+	pub fn tuples<'a>(&'a self) -> Tuples<'a, CursorMerger<'a, <T as TrieRef<'a>>::Cursor>> where T : TrieRef<'a>, <T as TrieRef<'a>>::Cursor: Flatten<'a> {
+		Tuples::new(self.cursor())
+	}
+
+	/// Provides a cursor for traversing the snapshot's contents with like tuples consolidated.
+	pub fn consolidated<'a>(&'a self) -> Consolidated<'a, <T as TrieRef<'a>>::Cursor> where T : TrieRef<'a>, <T as TrieRef<'a>>::Cursor: Consolidate<'a> {
+		Consolidated::new(self.cursor())
+	}
+
+	/// Provides a cursor over the values associated with `key`, merged across all tries.
+	pub fn get<'a>(&'a self, key: &T::Key) -> CursorMerger<'a, <<T as TrieRef<'a>>::Cursor as Cursor<'a>>::Val> 
+	where T: TrieRef<'a>, <<T as TrieRef<'a>>::Cursor as Cursor<'a>>::Val: Cursor<'a> {
+		get_from(self.probes(), self.hash, key)
+	}
+
+	/// Provides cursors over the values associated with each of the sorted `keys`.
+	pub fn get_many<'a>(&'a self, keys: &[T::Key]) -> Vec<CursorMerger<'a, <<T as TrieRef<'a>>::Cursor as Cursor<'a>>::Val>> 
+	where T: TrieRef<'a>, <<T as TrieRef<'a>>::Cursor as Cursor<'a>>::Val: Cursor<'a> {
+		get_many_from(self.probes(), self.hash, keys)
+	}
+
+	/// Provides a cursor for traversing the keys in `range`, and their values.
+	pub fn range<'a>(&'a self, range: Range<T::Key>) -> CursorMerger<'a, <T as TrieRef<'a>>::Cursor> where T : TrieRef<'a> {
+		range_from(self.ranges(), range)
+	}
+
+	// the captured ranges of tries.
+	fn ranges(&self) -> Vec<(&T, usize, usize)> {
+		self.tries.iter().map(|&(ref trie, lower, upper)| (&**trie, lower, upper)).collect()
+	}
+
+	// the captured ranges of tries, each with the filter of its layer if any.
+	fn probes<'a>(&'a self) -> Vec<Probe<'a, T>> {
+		self.tries.iter().zip(self.filters.iter()).map(|(&(ref trie, lower, upper), filter)| {
+			(&**trie, lower, upper, filter.as_deref())
+		}).collect()
+	}
+}
+
+// a range of a trie to search for keys, with the filter of the trie's layer if any.
+type Probe<'a, T> = (&'a T, usize, usize, Option<&'a Bloom>);
+
+// the position of `key` in the range of `trie` from `lower` to `upper` if present, and
+// otherwise the position at which a search for any greater key may start. the range is
+// not searched if `filter` excludes the key's `hash`.
+fn seek<T: TrieStorage>(trie: &T, lower: usize, upper: usize, filter: Option<&Bloom>, hash: Option<u64>, key: &T::Key) -> Result<usize, usize> {
+	if let (Some(filter), Some(hash)) = (filter, hash) {
+		if !filter.contains(hash) {
+			return Err(lower);
+		}
+	}
+	let index = trie.lower_bound(key, lower, upper);
+	if index < upper && trie.key(index) == key { Ok(index) } else { Err(index) }
+}
+
+// a merger of cursors over the values of `key` in each of `probes`, with keys hashed by
+// `hash` for the probes' filters.
+fn get_from<'a, T>(probes: Vec<Probe<'a, T>>, hash: Option<fn(&T::Key) -> u64>, key: &T::Key) -> CursorMerger<'a, <<T as TrieRef<'a>>::Cursor as Cursor<'a>>::Val> 
+where T: TrieStorage+TrieRef<'a>, <<T as TrieRef<'a>>::Cursor as Cursor<'a>>::Val: Cursor<'a> {
+	let hash = hash.map(|hash| hash(key));
+	let mut cursors = Vec::new();
+	for (trie, lower, upper, filter) in probes {
+		if let Ok(index) = seek(trie, lower, upper, filter, hash, key) {
+			cursors.push(trie.cursor(index, index + 1).next().unwrap().1);
+		}
+	}
+	CursorMerger::from(cursors.into_iter())
+}
+
+// mergers of cursors over the values of each of the sorted `keys` in each of `probes`,
+// found by a forward sweep through each probe, each search starting where the last stopped.
+fn get_many_from<'a, T>(probes: Vec<Probe<'a, T>>, hash: Option<fn(&T::Key) -> u64>, keys: &[T::Key]) -> Vec<CursorMerger<'a, <<T as TrieRef<'a>>::Cursor as Cursor<'a>>::Val>> 
+where T: TrieStorage+TrieRef<'a>, <<T as TrieRef<'a>>::Cursor as Cursor<'a>>::Val: Cursor<'a> {
+	debug_assert!(keys.windows(2).all(|x| x[0] <= x[1]), "keys must be sorted");
+	let hashes: Vec<Option<u64>> = keys.iter().map(|key| hash.map(|hash| hash(key))).collect();
+	let mut cursors: Vec<Vec<_>> = keys.iter().map(|_| Vec::new()).collect();
+	for (trie, mut lower, upper, filter) in probes {
+		for (position, key) in keys.iter().enumerate() {
+			// repeated keys search from the same position, and find the same values.
+			match seek(trie, lower, upper, filter, hashes[position], key) {
+				Ok(index) => {
+					cursors[position].push(trie.cursor(index, index + 1).next().unwrap().1);
+					lower = index;
+				},
+				Err(index) => { lower = index; },
+			}
+		}
+	}
+	cursors.into_iter().map(|cursors| CursorMerger::from(cursors.into_iter())).collect()
+}
+
+// a merger of cursors over the keys of each of `ranges` within `range`.
+fn range_from<'a, T>(ranges: Vec<(&'a T, usize, usize)>, range: Range<T::Key>) -> CursorMerger<'a, <T as TrieRef<'a>>::Cursor> 
+where T: TrieStorage+TrieRef<'a> {
+	CursorMerger::from(ranges.into_iter().map(|(trie, lower, upper)| {
+		let lower = trie.lower_bound(&range.start, lower, upper);
+		let upper = trie.lower_bound(&range.end, lower, upper);
+		trie.cursor(lower, upper)
+	}))
+}
+
+impl<T: Persist, P: MergePolicy> Arbor<T, P> {
+	/// Writes the arbor's tries to `writer`.
+	///
+	/// In-progress merges are written as their merged prefix and the unmerged remainders
+	/// of their inputs, and removed keys not yet dropped are left out. With filters enabled,
+	/// their density follows the tries. The result may be read with `read_from`, or viewed
+	/// in place with a `persist::ArborView`.
+	pub fn write_to<W: Write>(&self, mut writer: W) -> io::Result<()> {
+		let ranges = self.ranges();
+		persist::write_header(&mut writer, ranges.len())?;
+		for (trie, lower, upper) in ranges {
+			if lower == 0 && upper == trie.keys() {
+				trie.write_columns(&mut writer)?;
+			}
+			else {
+				let mut remainder = T::new();
+				remainder.extend_trie(trie, lower, upper);
+				remainder.write_columns(&mut writer)?;
+			}
+		}
+		if let Some(ref filtering) = self.filtering {
+			persist::write_column(&mut writer, &[filtering.density.bits as u64, filtering.density.hashes as u64])?;
+		}
+		Ok(())
+	}
+}
+
+impl<T: Persist, P: MergePolicy> Arbor<T, P> where T::Key: Hash {
+
+	/// Reads an arbor written by `write_to`, merging further tries as selected by `policy`.
+	///
+	/// Each trie is checked with `TrieStorage::validate`, and an error is returned if any
+	/// violates an invariant. If the arbor was written with filters enabled, they are
+	/// enabled at the same density and built for each trie.
+	pub fn read_with_policy(mut bytes: &[u8], policy: P) -> io::Result<Arbor<T, P>> {
+		let count = persist::read_header(&mut bytes)?;
+		let mut layers = Vec::with_capacity(count);
+		let mut masks = Vec::with_capacity(count);
+		for _ in 0 .. count {
+			layers.push(Layer::Complete(Arc::new(persist::read_trie(&mut bytes)?)));
+			masks.push(Vec::new());
+		}
+		let mut arbor = Arbor { layers: layers, masks: masks, filters: Vec::new(), filtering: None, policy: policy, worker: None };
+		if !bytes.is_empty() {
+			let density = persist::read_encoded::<u64>(&mut bytes, 2)?;
+			if density[1] == 0 || density[1] > density[0] || density[0] > bloom::MAX_BITS as u64 {
+				return Err(io::Error::new(io::ErrorKind::InvalidData, "invalid filter density"));
+			}
+			let density = Density { bits: density[0] as usize, hashes: density[1] as usize };
+			arbor.filtering = Some(Filtering { density: density, hash: bloom::hash::<T::Key> });
+		}
+		arbor.refilter();
+		Ok(arbor)
 	}
-}
\ No newline at end of file
+}
+
+impl<T: Persist> Arbor<T> where T::Key: Hash {
+	/// Reads an arbor written by `write_to`.
+	pub fn read_from(bytes: &[u8]) -> io::Result<Arbor<T>> {
+		Arbor::read_with_policy(bytes, Cascade::default())
+	}
+}
+
+#[cfg(test)]
+mod tests {
+
+	use std::collections::BTreeMap;
+	use std::ops::Range;
+
+	use TrieStorage;
+	use CursorMerger;
+	use trie::{Cursor, TrieLayer};
+	use flatten::Tuples;
+	use policy::{MergePolicy, Cascade, SizeRatio, MaxTries, Tiered};
+	use lattice::Lattice;
+	use time::TimeLayer;
+	use testing::{random, pairs};
+	use bloom::Density;
+	use super::{Arbor, Snapshot};
+
+	type Trie = TrieLayer<u32, Vec<(u32, i64)>>;
+
+	fn batch(seed: &mut u64, count: usize) -> Vec<(u32, (u32, i64))> {
+		pairs(seed, count, 50, 8)
+	}
+
+	// the consolidated contents of `arbor`, as read through its cursor.
+	fn contents<P: MergePolicy>(arbor: &Arbor<Trie, P>) -> Vec<(u32, u32, i64)> {
+		let tuples = arbor.tuples().map(|(key, (val, diff))| (*key, (*val, *diff)));
+		let trie = Trie::from_unordered(tuples);
+		trie.into_iter().map(|(key, (val, diff))| (*key, *val, *diff)).collect()
+	}
+
+	// the non-zero accumulations of `model`.
+	fn expected(model: &BTreeMap<(u32, u32), i64>) -> Vec<(u32, u32, i64)> {
+		model.iter().filter(|x| *x.1 != 0).map(|(&(key, val), &diff)| (key, val, diff)).collect()
+	}
+
+	fn check_progressive<P: MergePolicy>(mut arbor: Arbor<Trie, P>) {
+		let mut seed = 1;
+		let mut model = BTreeMap::new();
+		for round in 0 .. 200 {
+			let tuples = batch(&mut seed, if round % 50 == 0 { 500 } else { 1 + round % 40 });
+			for &(key, (val, diff)) in &tuples {
+				*model.entry((key, val)).or_insert(0) += diff;
+			}
+			arbor.extend_unordered(tuples.into_iter());
+			assert!(arbor.validate().is_ok());
+			assert_eq!(contents(&arbor), expected(&model));
+		}
+		arbor.consolidate();
+		assert!(arbor.validate().is_ok());
+		assert_eq!(contents(&arbor), expected(&model));
+		assert_eq!(arbor.size(), expected(&model).len());
+	}
+
+	#[test]
+	fn progressive_merges() {
+		// the default policy merges pairs of tries, and the others may merge runs of several.
+		check_progressive(Arbor::<Trie>::new());
+		check_progressive(Arbor::<Trie, _>::with_policy(MaxTries::new(3)));
+		check_progressive(Arbor::<Trie, _>::with_policy(Tiered::new(4)));
+		check_progressive(Arbor::<Trie, _>::with_policy(SizeRatio::new(3)));
+		check_progressive(Arbor::<Trie, _>::with_policy(|sizes: &[usize]| if sizes.len() > 4 { Some(0 .. sizes.len()) } else { None }));
+	}
+
+	// the non-zero accumulated differences of the values `cursor` presents.
+	fn values<'a, C: Cursor<'a, Key=u32, Val=&'a i64>>(mut cursor: CursorMerger<'a, C>) -> Vec<(u32, i64)> {
+		let mut found = BTreeMap::new();
+		while let Some(view) = cursor.next() {
+			let val = *view.key().unwrap();
+			for diff in view {
+				*found.entry(val).or_insert(0) += *diff;
+			}
+		}
+		found.into_iter().filter(|x| x.1 != 0).collect()
+	}
+
+	type Lookups = Arbor<Trie, fn(&[usize]) -> Option<Range<usize>>>;
+
+	// arbors of several tries: without merges, with the default merges, and with filters.
+	fn lookup_arbors(seed: u64) -> (Vec<Lookups>, BTreeMap<(u32, u32), i64>) {
+		fn never(_: &[usize]) -> Option<Range<usize>> { None }
+		fn cascade(sizes: &[usize]) -> Option<Range<usize>> { Cascade::default().select(sizes) }
+		let mut arbors: Vec<Lookups> = vec![Arbor::with_policy(never), Arbor::with_policy(cascade), Arbor::with_policy(never)];
+		arbors[2].enable_filters(0.01);
+		let mut seed = seed;
+		let mut model = BTreeMap::new();
+		for round in 0 .. 30 {
+			// even keys less than one hundred, leaving odd and larger keys missing.
+			let tuples: Vec<_> = batch(&mut seed, 1 + round % 20).into_iter().map(|(key, val)| (2 * key, val)).collect();
+			for &(key, (val, diff)) in &tuples {
+				*model.entry((key, val)).or_insert(0) += diff;
+			}
+			for arbor in &mut arbors {
+				arbor.extend_unordered(tuples.clone().into_iter());
+			}
+		}
+		assert_eq!(arbors[0].layers.len(), 30);
+		(arbors, model)
+	}
+
+	// the non-zero accumulated differences of a full scan of `arbor`, which match `model`.
+	fn scan(arbor: &Lookups, model: &BTreeMap<(u32, u32), i64>) -> BTreeMap<(u32, u32), i64> {
+		let mut scan = BTreeMap::new();
+		for (key, (val, diff)) in arbor.tuples() {
+			*scan.entry((*key, *val)).or_insert(0) += *diff;
+		}
+		scan.retain(|_, diff| *diff != 0);
+		assert!(scan.iter().eq(model.iter().filter(|x| *x.1 != 0)));
+		scan
+	}
+
+	#[test]
+	fn lookups_agree_with_scans() {
+		let (arbors, model) = lookup_arbors(5);
+		for arbor in &arbors {
+			let scan = scan(arbor, &model);
+			let snapshot = arbor.snapshot();
+			assert_eq!(snapshot.filters.iter().all(|filter| filter.is_some()), arbor.filtering.is_some());
+			for key in 0 .. 110 {
+				let expected: Vec<_> = scan.iter().filter(|x| (x.0).0 == key).map(|(&(_, val), &diff)| (val, diff)).collect();
+				assert_eq!(values(arbor.get(&key)), expected);
+				assert_eq!(values(snapshot.get(&key)), expected);
+			}
+			// ranges bounded by present keys, missing keys, and keys beyond either end.
+			let bounds = [0, 1, 2, 10, 11, 48, 49, 98, 99, 100, 150];
+			for &lower in &bounds {
+				for &upper in bounds.iter().filter(|&&upper| upper >= lower) {
+					let mut found = BTreeMap::new();
+					for (key, (val, diff)) in Tuples::new(arbor.range(lower .. upper)) {
+						assert!(lower <= *key && *key < upper);
+						*found.entry((*key, *val)).or_insert(0) += *diff;
+					}
+					let found: Vec<_> = found.into_iter().filter(|x| x.1 != 0).collect();
+					let expected: Vec<_> = scan.iter().filter(|x| lower <= (x.0).0 && (x.0).0 < upper).map(|(&k, &d)| (k, d)).collect();
+					assert_eq!(found, expected);
+				}
+			}
+		}
+	}
+
+	#[test]
+	fn batched_lookups_agree_with_scans() {
+		let (arbors, model) = lookup_arbors(6);
+		// present and missing keys, repeated keys, and keys beyond either end.
+		let keys = [0, 0, 1, 2, 2, 2, 3, 10, 11, 47, 48, 48, 49, 50, 97, 98, 99, 100, 150, 150];
+		for arbor in &arbors {
+			let scan = scan(arbor, &model);
+			let snapshot = arbor.snapshot();
+			let batched: Vec<_> = arbor.get_many(&keys).into_iter().map(values).collect();
+			let captured: Vec<_> = snapshot.get_many(&keys).into_iter().map(values).collect();
+			assert_eq!(batched.len(), keys.len());
+			for ((key, batched), captured) in keys.iter().zip(batched).zip(captured) {
+				let expected: Vec<_> = scan.iter().filter(|x| (x.0).0 == *key).map(|(&(_, val), &diff)| (val, diff)).collect();
+				assert_eq!(batched, expected);
+				assert_eq!(captured, expected);
+			}
+			assert!(arbor.get_many(&[]).is_empty());
+		}
+	}
+
+	fn check_removals(background: bool) {
+		let mut arbor = Arbor::<Trie>::new();
+		if background {
+			arbor.enable_background_merges(2);
+		}
+		let mut seed = 2;
+		let mut model = BTreeMap::new();
+		for round in 0 .. 200 {
+			match round % 5 {
+				0 => {
+					let key = (round * 7 % 50) as u32;
+					arbor.remove_key(&key);
+					model.retain(|&(other, _), _| other != key);
+					assert!(arbor.get(&key).next().is_none());
+				},
+				1 => {
+					let present = model.iter().filter(|x| *x.1 > 0).map(|x| *x.0).nth(round % 7);
+					if let Some((key, val)) = present {
+						arbor.retract((key, (val, 1)));
+						*model.get_mut(&(key, val)).unwrap() -= 1;
+					}
+				},
+				_ => {
+					let tuples = batch(&mut seed, 1 + round % 30);
+					for &(key, (val, diff)) in &tuples {
+						*model.entry((key, val)).or_insert(0) += diff;
+					}
+					arbor.extend_unordered(tuples.into_iter());
+				},
+			}
+			assert!(arbor.validate().is_ok());
+			assert_eq!(contents(&arbor), expected(&model));
+		}
+		arbor.consolidate();
+		assert!(arbor.validate().is_ok());
+		assert_eq!(contents(&arbor), expected(&model));
+		assert_eq!(arbor.size(), expected(&model).len());
+	}
+
+	// the consolidated contents of `snapshot`, as `contents` reads them from an arbor.
+	fn captured(snapshot: &Snapshot<Trie>) -> Vec<(u32, u32, i64)> {
+		let tuples = snapshot.tuples().map(|(key, (val, diff))| (*key, (*val, *diff)));
+		let trie = Trie::from_unordered(tuples);
+		trie.into_iter().map(|(key, (val, diff))| (*key, *val, *diff)).collect()
+	}
+
+	fn check_snapshots(background: bool) {
+		let mut arbor = Arbor::<Trie>::new();
+		if background {
+			arbor.enable_background_merges(2);
+		}
+		let mut seed = 7;
+		let mut snapshots = Vec::new();
+		for round in 0 .. 100 {
+			arbor.extend_unordered(batch(&mut seed, 1 + round % 30).into_iter());
+			match round % 10 {
+				3 => arbor.remove_key(&((round * 7 % 50) as u32)),
+				6 => arbor.retract(arbor.tuples().map(|(key, (val, diff))| (*key, (*val, *diff))).next().unwrap()),
+				9 => arbor.wait_for_merges(),
+				_ => { },
+			}
+			if round % 4 == 0 {
+				let snapshot = arbor.snapshot();
+				let expected = contents(&arbor);
+				assert_eq!(captured(&snapshot), expected);
+				snapshots.push((snapshot.size(), snapshot, expected));
+			}
+			// snapshots taken earlier are unaffected by the appends, removals, and merges since.
+			for &(size, ref snapshot, ref expected) in &snapshots {
+				assert_eq!(snapshot.size(), size);
+				assert_eq!(&captured(snapshot), expected);
+			}
+		}
+
+		arbor.wait_for_merges();
+		arbor.consolidate();
+		arbor.remove_key(&0);
+		let handles: Vec<_> = snapshots.into_iter().map(|(size, snapshot, expected)| {
+			::std::thread::spawn(move || {
+				assert_eq!(snapshot.size(), size);
+				assert_eq!(captured(&snapshot), expected);
+			})
+		}).collect();
+		for handle in handles {
+			handle.join().unwrap();
+		}
+	}
+
+	#[test]
+	fn snapshots_stay_fixed() {
+		check_snapshots(false);
+		check_snapshots(true);
+	}
+
+	#[test]
+	fn removals() {
+		check_removals(false);
+		check_removals(true);
+	}
+
+	#[test]
+	fn write_and_read() {
+		let mut arbor = Arbor::<Trie>::new();
+		let mut filtered = Arbor::<Trie>::new();
+		filtered.enable_filters(0.05);
+		let mut seed = 3;
+		for round in 0 .. 100 {
+			let tuples = batch(&mut seed, 1 + round % 30);
+			arbor.extend_unordered(tuples.clone().into_iter());
+			filtered.extend_unordered(tuples.into_iter());
+			if round % 3 == 0 {
+				arbor.remove_key(&((round * 11 % 50) as u32));
+				filtered.remove_key(&((round * 11 % 50) as u32));
+			}
+			let mut bytes = Vec::new();
+			arbor.write_to(&mut bytes).unwrap();
+			let read = Arbor::<Trie>::read_from(&bytes[..]).unwrap();
+			assert!(read.validate().is_ok());
+			assert_eq!(contents(&read), contents(&arbor));
+			assert!(read.filtering.is_none() && read.filters.iter().all(|filter| filter.is_none()));
+
+			// filters are built again at the density they were written with.
+			let mut bytes = Vec::new();
+			filtered.write_to(&mut bytes).unwrap();
+			let read = Arbor::<Trie>::read_from(&bytes[..]).unwrap();
+			assert_eq!(contents(&read), contents(&filtered));
+			assert_eq!(read.filtering.as_ref().map(|filtering| filtering.density), Some(Density::for_rate(0.05)));
+			assert_eq!(read.filters.len(), read.layers.len());
+			assert!(read.filters.iter().all(|filter| filter.is_some()));
+			let expected = contents(&arbor);
+			for key in 0 .. 60 {
+				let expected: Vec<_> = expected.iter().filter(|x| x.0 == key).map(|x| (x.1, x.2)).collect();
+				assert_eq!(values(read.get(&key)), expected);
+			}
+		}
+
+		// a density no rate would produce is rejected.
+		let mut bytes = Vec::new();
+		filtered.write_to(&mut bytes).unwrap();
+		let len = bytes.len();
+		bytes[len - 16 .. len - 8].copy_from_slice(&0u64.to_ne_bytes());
+		assert!(Arbor::<Trie>::read_from(&bytes[..]).is_err());
+	}
+
+	#[test]
+	fn advance_by_frontier() {
+		type Timed = TrieLayer<u32, TimeLayer<u32, Vec<(u32, i64)>>>;
+		let mut arbor = Arbor::<Timed>::new();
+		let mut seed = 4;
+		let mut model = BTreeMap::new();
+		for round in 0 .. 40 {
+			let tuples: Vec<_> = pairs(&mut seed, 1 + round % 20, 30, 4).into_iter().map(|(key, (val, diff))| {
+				(key, ((random(&mut seed) % 10) as u32, (val, diff)))
+			}).collect();
+			for &(key, (time, (val, diff))) in &tuples {
+				*model.entry((key, time, val)).or_insert(0) += diff;
+			}
+			arbor.extend_unordered(tuples.into_iter());
+		}
+
+		// times before the frontier collapse into it, and their differences consolidate.
+		arbor.advance_by(&[5]);
+		assert!(arbor.validate().is_ok());
+		let mut advanced = BTreeMap::new();
+		for ((key, time, val), diff) in model {
+			*advanced.entry((key, time.advance_by(&[5]), val)).or_insert(0) += diff;
+		}
+		let expected: Vec<_> = advanced.into_iter().filter(|x| x.1 != 0).map(|((key, time, val), diff)| (key, time, val, diff)).collect();
+
+		let tuples = arbor.tuples().map(|(key, (time, (val, diff)))| (*key, (*time, (*val, *diff))));
+		let found: Vec<_> = Timed::from_unordered(tuples).into_iter().map(|(key, (time, (val, diff)))| (*key, *time, *val, *diff)).collect();
+		assert_eq!(found, expected);
+		assert!(arbor.tuples().all(|(_, (time, _))| *time >= 5));
+	}
+}
@@ -1,72 +1,160 @@
-//! A collection of `Trie<K,T,V>` tries.
+//! A collection of tries indexed by key, for random access.
 //! 
-//! An `Arbor` is backed by multiple `Trie` structures of varying sizes, 
-//! designed to provide both efficient enumeration of its contents and 
-//! addition of new tuples. 
+//! An `ArborIndex` holds tries of varying sizes and merges them as an `Arbor` does,
+//! but also maintains a directory from each key to its location in the newest trie
+//! containing it. A lookup reads the directory once, and then visits only the tries
+//! holding the key, rather than searching every trie. Locations of the key in older
+//! tries are chained through a `spill` list, so that the directory holds one entry for
+//! each distinct key.
 //! 
-//! The main functionality of the `Arbor` is to continually merge tries 
-//! whose sizes are the same order of magnitude. This keeps a bounded 
-//! number of tries, so that enumeration remains efficient, while doing
-//! an amortized logarithmic amount of work for each introduced tuple, 
-//! which should be asymptotically optimal as the product of the `Arbor`
-//! is an ordered representation of its contents.
-
-use std::collections::HashMap;
-use std::hash::Hash;
+//! The directory is a `HashMap` with the FNV hasher by default, and may be any other
+//! `Directory`: a `HashMap` with another hasher, or a `BTreeMap` for keys without a
+//! hash. Merges update the directory only for the keys of the merged trie and those
+//! the merge drops, so that its maintenance costs about as much as the merge itself.
+
+use std::collections::{HashMap, BTreeMap};
+use std::hash::{Hash, BuildHasher};
 use std::hash::BuildHasherDefault;
-use std::collections::hash_map::Entry;
 
 use fnv::FnvHasher;
 
 use {TrieRef, TrieStorage, CursorMerger};
-use ::trie::TrieLayer;
+use ::trie::{TrieLayer, Violation};
+use policy::{MergePolicy, Cascade};
+use radix::Radix;
+
+// marks a location without a next entry in `spill`.
+const NO_NEXT: u32 = u32::MAX;
 
-struct KeyLocation {
-	index: usize,
-	offset: usize,
-	next: Option<usize>,
+/// The location of a key in an `ArborIndex`: a trie, the key's position in the trie, and
+/// optionally the position in `spill` of the key's location in an older trie.
+///
+/// Each is packed into a `u32`, so that a location takes twelve bytes rather than the
+/// thirty-two of a tuple of `usize`s on 64-bit targets. An index therefore holds fewer
+/// than `u32::MAX` keys in each trie, and spills fewer locations than that.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Location {
+	trie: u32,
+	offset: u32,
+	next: u32,
 }
 
-impl KeyLocation {
-	fn new(index: usize, offset: usize, next: Option<usize>) -> KeyLocation {
-		KeyLocation {
-			index: index,
-			offset: offset,
+impl Location {
+	fn new(trie: usize, offset: usize, next: Option<usize>) -> Location {
+		assert!(offset < NO_NEXT as usize, "too many keys in trie to index");
+		let next = match next {
+			Some(next) => { assert!(next < NO_NEXT as usize, "too many spilled locations"); next as u32 },
+			None => NO_NEXT,
+		};
+		Location {
+			trie: trie as u32,
+			offset: offset as u32,
 			next: next,
 		}
 	}
+
+	/// The position of the key's trie, counting from the largest.
+	pub fn trie(&self) -> usize { self.trie as usize }
+	/// The position of the key in its trie.
+	pub fn offset(&self) -> usize { self.offset as usize }
+	/// The position in `spill` of the key's location in an older trie, if any.
+	pub fn next(&self) -> Option<usize> {
+		if self.next == NO_NEXT { None } else { Some(self.next as usize) }
+	}
+}
+
+/// A map from keys to their most recent locations, used as the index of an `ArborIndex`.
+///
+/// The default directory is a `HashMap` with the FNV hasher, which is fast but can be
+/// driven to collide by keys chosen by an adversary. A `HashMap` may use any other
+/// hasher, and a `BTreeMap` needs only `K: Ord` rather than `K: Hash`.
+pub trait Directory<K> : Default {
+	/// Returns the location of `key`, if any.
+	fn get(&self, key: &K) -> Option<&Location>;
+	/// Returns the location of `key` mutably, if any.
+	fn get_mut(&mut self, key: &K) -> Option<&mut Location>;
+	/// Sets the location of `key`, returning any previous location.
+	fn insert(&mut self, key: K, location: Location) -> Option<Location>;
+	/// Removes and returns the location of `key`, if any.
+	fn remove(&mut self, key: &K) -> Option<Location>;
+	/// Reports the number of bytes the directory has allocated, approximately.
+	///
+	/// This counts the space for keys and locations, but not memory owned by keys.
+	fn memory_usage(&self) -> usize;
+	/// Releases any capacity the directory does not need.
+	fn shrink_to_fit(&mut self) { }
+}
+
+impl<K: Hash+Eq, S: BuildHasher+Default> Directory<K> for HashMap<K, Location, S> {
+	fn get(&self, key: &K) -> Option<&Location> { HashMap::get(self, key) }
+	fn get_mut(&mut self, key: &K) -> Option<&mut Location> { HashMap::get_mut(self, key) }
+	fn insert(&mut self, key: K, location: Location) -> Option<Location> { HashMap::insert(self, key, location) }
+	fn remove(&mut self, key: &K) -> Option<Location> { HashMap::remove(self, key) }
+	fn memory_usage(&self) -> usize {
+		// each slot holds a key and location, with a byte of control information.
+		self.capacity() * (::std::mem::size_of::<(K, Location)>() + 1)
+	}
+	fn shrink_to_fit(&mut self) { HashMap::shrink_to_fit(self) }
+}
+
+impl<K: Ord> Directory<K> for BTreeMap<K, Location> {
+	fn get(&self, key: &K) -> Option<&Location> { BTreeMap::get(self, key) }
+	fn get_mut(&mut self, key: &K) -> Option<&mut Location> { BTreeMap::get_mut(self, key) }
+	fn insert(&mut self, key: K, location: Location) -> Option<Location> { BTreeMap::insert(self, key, location) }
+	fn remove(&mut self, key: &K) -> Option<Location> { BTreeMap::remove(self, key) }
+	fn memory_usage(&self) -> usize {
+		// nodes are at least half full, and this ignores their edges and lengths.
+		self.len() * ::std::mem::size_of::<(K, Location)>()
+	}
 }
 
+/// The default directory, a `HashMap` using the FNV hasher.
+pub type FnvDirectory<K> = HashMap<K, Location, BuildHasherDefault<FnvHasher>>;
+
+/// An `ArborIndex` whose directory hashes keys with `S`, such as the randomly keyed
+/// `std::collections::hash_map::RandomState` for keys chosen by untrusted parties.
+pub type HashArborIndex<K, L, S> = ArborIndex<K, L, Cascade, HashMap<K, Location, S>>;
+
+/// An `ArborIndex` whose directory is a `BTreeMap`, for keys that cannot be hashed.
+pub type SortedArborIndex<K, L> = ArborIndex<K, L, Cascade, BTreeMap<K, Location>>;
+
 #[derive(Debug)]
-pub struct ArborIndex<K: Ord+Hash, L: TrieStorage> {
+pub struct ArborIndex<K: Ord, L: TrieStorage, P: MergePolicy=Cascade, D: Directory<K>=FnvDirectory<K>> {
 
 	// storage for keyed trie values, from largest to smallest.
-	// the `usize` is the number of spilled KeyLocation entries.
+	// the `usize` is the number of spilled locations for keys of the trie.
 	tries: Vec<(TrieLayer<K, L>, usize)>,
 
-	// indicates a smallest entry in `spill`.
+	// the location of each key in the newest trie containing it.
 	//
-	// TODO : put the first entry of `spill` here, and only successive entries
-	// into `spill`. This complicates the logic for updating the index, but makes
-	// for fewer dereferences when a key doesn't spill. All keys are guaranteed 
-	// to have an entry, so it isn't wasted space.
-	// index: HashMap<K, usize>,
-	index: HashMap<K, (usize, usize, Option<usize>), BuildHasherDefault<FnvHasher>>,
-
-	// indicates a (layer, offset) and optionally a next entry in `spill`.
-	spill: Vec<(usize, usize, Option<usize>)>,	
+	// the first location is held inline, so that keys in only one trie need not
+	// consult `spill`. all keys have a location, so the space is not wasted.
+	index: D,
+
+	// locations of keys in older tries, in runs for each trie from largest to smallest.
+	// the runs of merged tries are rebuilt by each merge.
+	spill: Vec<Location>,
+
+	// selects runs of tries to merge.
+	policy: P,
 }
 
-impl<K: Ord+Hash+Clone, L: TrieStorage> ArborIndex<K, L> {
+impl<K: Ord+Clone, L: TrieStorage, D: Directory<K>> ArborIndex<K, L, Cascade, D> {
+	/// Allocates a new empty arbor, merging tries by the default `Cascade` policy.
+	pub fn new() -> ArborIndex<K, L, Cascade, D> {
+		ArborIndex::with_policy(Cascade::default())
+	}
+}
 
-	/// Allocates a new empty arbor.
-	pub fn new() -> ArborIndex<K, L> {
+impl<K: Ord+Clone, L: TrieStorage, P: MergePolicy, D: Directory<K>> ArborIndex<K, L, P, D> {
 
-		// let map: HashMap<K, usize, BuildHasherDefault<FnvHasher>> = Default::default();
+	/// Allocates a new empty arbor, merging tries as selected by `policy`.
+	pub fn with_policy(policy: P) -> ArborIndex<K, L, P, D> {
 		ArborIndex { 
 			tries: Vec::new(),
 			index: ::std::default::Default::default(),
 			spill: Vec::new(),
+			policy: policy,
 		}
 	}
 
@@ -84,12 +172,26 @@ impl<K: Ord+Hash+Clone, L: TrieStorage> ArborIndex<K, L> {
 
 	/// Adds an ordered sequence of tuples to the collection.
 	///
-	/// If the tuples aren't in order, something horrible probably happens.
-	/// Nothing memory unsafe, but ... why would you do this?
+	/// The tuples must be in order, and out of order tuples are rejected in debug 
+	/// builds. Tuples in any other order can be added with `extend_unordered`.
 	pub fn extend_ordered<I: Iterator<Item=(K, L::Item)>>(&mut self, iterator: I) {
 		self.append(TrieLayer::<K, L>::from_ordered(iterator));
 	}
 
+	/// Adds a sequence of tuples, in any order, to the collection.
+	///
+	/// The tuples are sorted and consolidated by `TrieStorage::from_unordered`.
+	pub fn extend_unordered<I: Iterator<Item=(K, L::Item)>>(&mut self, iterator: I) {
+		self.append(TrieLayer::<K, L>::from_unordered(iterator));
+	}
+
+	/// Adds a sequence of tuples with integer keys, in any order, to the collection.
+	///
+	/// The tuples are sorted and consolidated by `TrieStorage::from_unordered_radix`.
+	pub fn extend_unordered_radix<I: Iterator<Item=(K, L::Item)>>(&mut self, iterator: I) where K: Radix {
+		self.append(TrieLayer::<K, L>::from_unordered_radix(iterator));
+	}
+
 	/// Adds an entire trie into the collection.
 	///
 	/// This method can be helpful if the resources required for the trie
@@ -97,62 +199,181 @@ impl<K: Ord+Hash+Clone, L: TrieStorage> ArborIndex<K, L> {
 	/// in `extend_ordered`. The method can be quite fast in this case, as
 	/// it does not need to re-process every tuple in the input batch.
 	///
-	/// The method will perform merging of tries if the introduced trie has
-	/// a size within a factor of two of the smallest trie the arbor currently
-	/// manages. This can be quite *not fast*, but it should be improved with
+	/// The method will perform merging of tries if the arbor's policy selects 
+	/// a run of tries including the introduced trie. Runs that do not include
+	/// it are extended to include it, so that only the newest tries are ever
+	/// re-indexed. This can be quite *not fast*, but it should be improved with
 	/// progressive merging.
-	pub fn append(&mut self, mut trie: TrieLayer<K, L>) {
-
-		while self.tries.last().map(|x| x.0.tuples() <= 2 * trie.tuples()) == Some(true) {
-			
-			let (other, count) = self.tries.pop().unwrap();
-			
-			// pop entries from self.index.
-			//
-			// TODO : merge could track a list of discarded keys, as we can then
-			// update them, followed by the keys in the merged results, rather than
-			// all of the indexing we do here. Measure this, then implement that.
-			for &(ref key, _) in &other.keys {
-				match self.index.entry(key.clone()) {
-					Entry::Occupied(mut entry) => { 
-						if let Some(next) = entry.get().2 {
-							*entry.get_mut() = self.spill[next];
+	///
+	/// The index is updated once for each key of the merged trie, and once for
+	/// each key the merge drops, rather than for each key of each merged trie.
+	pub fn append(&mut self, trie: TrieLayer<K, L>) {
+		self.append_with(trie, true);
+	}
+
+	/// Adds an entire trie into the collection, re-indexing all keys of merged tries.
+	///
+	/// The keys of each trie merged are removed from the index before merging, and all
+	/// keys of the merged trie added afterwards. The method is otherwise as `append`, 
+	/// which does less work and should be preferred; it remains for comparison.
+	pub fn append_reindexed(&mut self, trie: TrieLayer<K, L>) {
+		self.append_with(trie, false);
+	}
+
+	// appends `trie`, maintaining the index incrementally or by re-indexing merged tries.
+	fn append_with(&mut self, mut trie: TrieLayer<K, L>, incremental: bool) {
+
+		// the least position of a merged trie, and the length of `spill` before their entries.
+		let mut least = self.tries.len();
+		let mut spill_base = self.spill.len();
+		let mut dropped = Vec::new();
+
+		loop {
+
+			let run = {
+				let mut sizes: Vec<usize> = self.tries.iter().map(|x| x.0.tuples()).collect();
+				sizes.push(trie.tuples());
+				match self.policy.select(&sizes[..]) {
+					Some(run) => {
+						assert!(run.start + 1 < run.end && run.end <= sizes.len(), "invalid merge run: {:?}", run);
+						run.start
+					},
+					None => break,
+				}
+			};
+
+			let mut others = Vec::with_capacity(self.tries.len() - run);
+			while self.tries.len() > run {
+
+				let (other, count) = self.tries.pop().unwrap();
+				spill_base -= count;
+
+				// pop entries from self.index, unless they are to be updated incrementally.
+				if !incremental {
+					for (key, _) in &other.keys {
+						let spilled = match self.index.get_mut(key) {
+							Some(entry) => { 
+								if let Some(next) = entry.next() {
+									*entry = self.spill[next];
+									true
+								}
+								else {
+									false
+								}
+							},
+							None => { 
+								unreachable!();
+							},
+						};
+						if !spilled {
+							self.index.remove(key);
 						}
-						else {
-							entry.remove();
+					}
+
+					for _ in 0 .. count { self.spill.pop(); }
+				}
+
+				others.push(other);
+			}
+			least = run;
+
+			others.push(trie);
+			trie = {
+				let ranges: Vec<_> = others.iter().map(|other| (other, 0, other.keys.len())).collect();
+				let mut merged = TrieLayer::new();
+				merged.extend_merge_many_dropping(&ranges[..], &mut dropped);
+				merged
+			};
+		}
+
+		if incremental {
+
+			// dropped keys fall back to their locations in older tries, if any.
+			for key in &dropped {
+				let remove = match self.index.get_mut(key) {
+					Some(entry) => {
+						match older(&self.spill[..], *entry, least) {
+							Some(location) => { *entry = location; false },
+							None => true,
 						}
 					},
-					Entry::Vacant(mut entry) => { 
-						unreachable!();
-					},
+					None => false,
+				};
+				if remove {
+					self.index.remove(key);
 				}
 			}
 
-			for _ in 0 .. count { self.spill.pop(); }
+			// each key of the result is located in it, followed by its location in older tries.
+			// locations in older tries are spilled after the retained entries of `spill`.
+			let mut spilled = Vec::new();
+			for (pos, key) in trie.keys.iter().map(|x| &x.0).enumerate() {
+				let present = match self.index.get_mut(key) {
+					Some(entry) => {
+						*entry = match older(&self.spill[..], *entry, least) {
+							Some(location) => {
+								spilled.push(location);
+								Location::new(least, pos, Some(spill_base + spilled.len() - 1))
+							},
+							None => Location::new(least, pos, None),
+						};
+						true
+					},
+					None => false,
+				};
+				if !present {
+					self.index.insert(key.clone(), Location::new(least, pos, None));
+				}
+			}
 
-			trie = trie.merge(&other);
+			self.spill.truncate(spill_base);
+			self.spill.extend(spilled);
 		}
+		else {
 
-		// update index for all keys in the result of the merge.
-		let mut spill_len = self.spill.len();
-		for (pos, key) in trie.keys.iter().map(|x| &x.0).enumerate() {
-			match self.index.entry(key.clone()) {
-				Entry::Occupied(mut entry) => { 
-					self.spill.push(*entry.get());
-					*entry.get_mut() = (self.tries.len(), pos, Some(self.spill.len() - 1));
-				},
-				Entry::Vacant(mut entry) => { 
-					entry.insert((self.tries.len(), pos, None));
-				},
+			// update index for all keys in the result of the merge.
+			for (pos, key) in trie.keys.iter().map(|x| &x.0).enumerate() {
+				if let Some(previous) = self.index.insert(key.clone(), Location::new(self.tries.len(), pos, None)) {
+					self.spill.push(previous);
+					*self.index.get_mut(key).unwrap() = Location::new(self.tries.len(), pos, Some(self.spill.len() - 1));
+				}
 			}
 		}
 
-		let count = self.spill.len() - spill_len;
+		let count = self.spill.len() - spill_base;
 		self.tries.push((trie, count));
 	}
+
+	/// Checks the invariants of each managed trie, and reports the first violation found.
+	///
+	/// A violation is reported with the position of its trie, counting from the largest.
+	pub fn validate(&self) -> Result<(), (usize, Violation)> {
+		for (index, trie) in self.tries.iter().enumerate() {
+			trie.0.validate().map_err(|violation| (index, violation))?;
+		}
+		Ok(())
+	}
+
+	/// Reports the number of bytes the index has allocated, approximately.
+	///
+	/// This is the memory the arbor uses beyond that of its tries, to find the tries
+	/// containing each key. It is at most a directory entry for each distinct key and
+	/// a spilled location for each key of each trie, plus any unused capacity.
+	pub fn memory_usage(&self) -> usize {
+		self.index.memory_usage() + self.spill.capacity() * ::std::mem::size_of::<Location>()
+	}
+
+	/// Releases any capacity the index does not need.
+	///
+	/// Merges leave `spill` with the capacity of its longest length, and directories may
+	/// similarly retain the capacity for keys since dropped.
+	pub fn shrink_to_fit(&mut self) {
+		self.index.shrink_to_fit();
+		self.spill.shrink_to_fit();
+	}
 }
 
-impl<'a, K: Ord+Hash, L: TrieStorage+TrieRef<'a>> ArborIndex<K, L> {
+impl<'a, K: Ord, L: TrieStorage+TrieRef<'a>, P: MergePolicy, D: Directory<K>> ArborIndex<K, L, P, D> {
 	/// Provides a cursor for traversing the arbor's contents.
 	pub fn cursor(&'a self) -> CursorMerger<'a, ::trie::TrieCursor<'a, K, L>> {
 		CursorMerger::from(self.tries.iter().map(|x| x.0.cursor(0, x.0.keys_cnt())))
@@ -163,15 +384,182 @@ impl<'a, K: Ord+Hash, L: TrieStorage+TrieRef<'a>> ArborIndex<K, L> {
 	/// If the key does not exist in the collection, the merger will simply be empty.
 	pub fn get_into(&'a self, key: &K, cursor: &mut CursorMerger<'a, <L as TrieRef<'a>>::Cursor>) {
 		cursor.clear();
+		let location = self.index.get(key).copied();
+		self.push_located(location, cursor);
+	}
 
-		let mut next = self.index.get(key).map(|&x| x);
-		while let Some((index, offset, spill)) = next {
-			let lower = if offset == 0 { 0 } else { self.tries[index].0.keys[offset - 1].1 };
-			let upper = self.tries[index].0.keys[offset].1;
-			cursor.push(self.tries[index].0.vals.cursor(lower, upper));
-			next = spill.map(|next| self.spill[next]);
+	/// Provides mergers of cursors for the values of each of `keys`, in the order of `keys`.
+	///
+	/// The directory is probed for every key before any trie is visited, so that probes
+	/// do not wait on each other's cache misses, nor on those of reading the tries.
+	pub fn get_many(&'a self, keys: &[K]) -> Vec<CursorMerger<'a, <L as TrieRef<'a>>::Cursor>> {
+		let locations: Vec<Option<Location>> = keys.iter().map(|key| self.index.get(key).copied()).collect();
+		locations.into_iter().map(|location| {
+			let mut cursor = CursorMerger::new();
+			self.push_located(location, &mut cursor);
+			cursor
+		}).collect()
+	}
+
+	// pushes cursors for the values at `location` and the locations chained from it.
+	fn push_located(&'a self, location: Option<Location>, cursor: &mut CursorMerger<'a, <L as TrieRef<'a>>::Cursor>) {
+		let mut next = location;
+		while let Some(location) = next {
+			let trie = &self.tries[location.trie()].0;
+			let lower = if location.offset() == 0 { 0 } else { trie.keys[location.offset() - 1].1 };
+			let upper = trie.keys[location.offset()].1;
+			cursor.push(trie.vals.cursor(lower, upper));
+			next = location.next().map(|next| self.spill[next]);
 		}
 
 		cursor.cursors.sort_by(|x,y| (x.0).0.cmp(&(y.0).0));
 	}
-}
\ No newline at end of file
+}
+
+// the first location in the chain from `location` in a trie older than position `least`.
+fn older(spill: &[Location], mut location: Location, least: usize) -> Option<Location> {
+	while location.trie() >= least {
+		match location.next() {
+			Some(next) => { location = spill[next]; },
+			None => { return None; },
+		}
+	}
+	Some(location)
+}
+
+#[cfg(test)]
+mod tests {
+
+	use std::collections::BTreeMap;
+	use std::collections::hash_map::RandomState;
+
+	use TrieStorage;
+	use CursorMerger;
+	use trie::TrieLayer;
+	use flatten::Tuples;
+	use policy::{MergePolicy, Cascade, SizeRatio, MaxTries, Tiered};
+	use testing::random;
+	use super::{ArborIndex, Directory, HashArborIndex, SortedArborIndex};
+
+	type Index<P> = ArborIndex<u32, Vec<(u32, i64)>, P>;
+
+	// the non-zero accumulated differences of the values found for each of `keys`.
+	fn lookups<P: MergePolicy, D: Directory<u32>>(index: &ArborIndex<u32, Vec<(u32, i64)>, P, D>, keys: &[u32]) -> Vec<Vec<(u32, i64)>> {
+		index.get_many(keys).into_iter().map(|mut cursor| {
+			let mut found = BTreeMap::new();
+			while let Some(view) = cursor.next() {
+				let val = *view.key().unwrap();
+				for diff in view {
+					*found.entry(val).or_insert(0) += *diff;
+				}
+			}
+			found.into_iter().filter(|x| x.1 != 0).collect()
+		}).collect()
+	}
+
+	// checks that `append` maintains the same index as `append_reindexed`, given two empty indices.
+	fn check_maintenance<P: MergePolicy, D: Directory<u32>>(mut incremental: ArborIndex<u32, Vec<(u32, i64)>, P, D>, mut reindexed: ArborIndex<u32, Vec<(u32, i64)>, P, D>) {
+		let keys: Vec<u32> = (0 .. 45).collect();
+		let mut seed = 11u64;
+		let mut batches: Vec<Vec<(u32, (u32, i64))>> = Vec::new();
+		for round in 0 .. 200 {
+			let mut batch = Vec::new();
+			for _ in 0 .. 1 + round % 13 {
+				let bits = random(&mut seed);
+				batch.push(((bits % 41) as u32, ((bits / 41 % 3) as u32, 1)));
+			}
+			// retract the batch of two rounds ago, so that merges drop keys.
+			if round >= 2 {
+				batch.extend(batches[round - 2].iter().filter(|x| (x.1).1 > 0).map(|&(key, (val, _))| (key, (val, -1))));
+			}
+			batches.push(batch.clone());
+			incremental.append(TrieLayer::from_unordered(batch.clone().into_iter()));
+			reindexed.append_reindexed(TrieLayer::from_unordered(batch.into_iter()));
+			assert!(incremental.validate().is_ok());
+			assert!(reindexed.validate().is_ok());
+			assert_eq!(lookups(&incremental, &keys[..]), lookups(&reindexed, &keys[..]));
+		}
+	}
+
+	#[test]
+	fn lookups_agree_with_scans() {
+		let never = |_: &[usize]| None;
+		check_lookups(Index::with_policy(never), 40);
+		check_lookups(Index::with_policy(Cascade::default()), 1);
+	}
+
+	// checks `get_many` and `get_into` against a full scan, with at least `tries` tries.
+	fn check_lookups<P: MergePolicy>(mut index: Index<P>, tries: usize) {
+		let mut seed = 12u64;
+		for round in 0 .. 40 {
+			// even keys less than eighty, leaving odd and larger keys missing.
+			let batch: Vec<_> = (0 .. 1 + round % 9).map(|_| {
+				let bits = random(&mut seed);
+				(2 * (bits % 40) as u32, ((bits / 40 % 3) as u32, if bits & 1 == 0 { 1 } else { -1 }))
+			}).collect();
+			index.extend_unordered(batch.into_iter());
+		}
+		assert!(index.tries.len() >= tries);
+
+		let mut scan = BTreeMap::new();
+		for (key, (val, diff)) in Tuples::new(index.cursor()) {
+			*scan.entry(*key).or_insert_with(BTreeMap::new).entry(*val).or_insert(0) += *diff;
+		}
+		let keys: Vec<u32> = (0 .. 90).chain(vec![0, 40, 41, 200]).collect();
+		let found = lookups(&index, &keys[..]);
+		let mut cursor = CursorMerger::new();
+		for (key, found) in keys.iter().zip(found) {
+			let expected: Vec<(u32, i64)> = scan.get(key).map(|vals| vals.iter().filter(|x| *x.1 != 0).map(|(&val, &diff)| (val, diff)).collect()).unwrap_or_default();
+			assert_eq!(found, expected);
+			index.get_into(key, &mut cursor);
+			let mut single = BTreeMap::new();
+			while let Some(view) = cursor.next() {
+				let val = *view.key().unwrap();
+				for diff in view {
+					*single.entry(val).or_insert(0) += *diff;
+				}
+			}
+			assert!(single.into_iter().filter(|x| x.1 != 0).eq(expected));
+		}
+	}
+
+	// checks that `shrink_to_fit` does not increase `memory_usage`, nor change lookups,
+	// and reports the usage before and after.
+	fn check_shrink<P: MergePolicy, D: Directory<u32>>(mut index: ArborIndex<u32, Vec<(u32, i64)>, P, D>) -> (usize, usize) {
+		let keys: Vec<u32> = (0 .. 1000).collect();
+		index.extend_unordered(keys.iter().map(|&key| (key, (0, 1))));
+		index.extend_unordered(keys.iter().map(|&key| (key, (1, 1))));
+		// retractions of most keys, whose merge drops them from the index.
+		index.extend_unordered(keys.iter().filter(|&&key| key % 10 != 0).map(|&key| (key, (0, -1))));
+		index.extend_unordered(keys.iter().filter(|&&key| key % 10 != 0).map(|&key| (key, (1, -1))));
+		assert!(index.validate().is_ok());
+		let found = lookups(&index, &keys[..]);
+		let usage = index.memory_usage();
+		index.shrink_to_fit();
+		let shrunk = index.memory_usage();
+		assert!(shrunk <= usage);
+		assert_eq!(lookups(&index, &keys[..]), found);
+		index.shrink_to_fit();
+		assert_eq!(index.memory_usage(), shrunk);
+		(usage, shrunk)
+	}
+
+	#[test]
+	fn shrinking_does_not_grow() {
+		// the merge drops most keys, whose capacity shrinking releases.
+		let (usage, shrunk) = check_shrink(Index::<Cascade>::new());
+		assert!(shrunk < usage);
+		check_shrink(Index::with_policy(|_: &[usize]| None));
+		check_shrink(SortedArborIndex::new());
+	}
+
+	#[test]
+	fn incremental_maintenance() {
+		check_maintenance(Index::<Cascade>::new(), Index::new());
+		check_maintenance(Index::with_policy(SizeRatio::default()), Index::with_policy(SizeRatio::default()));
+		check_maintenance(Index::with_policy(MaxTries::new(3)), Index::with_policy(MaxTries::new(3)));
+		check_maintenance(Index::with_policy(Tiered::new(3)), Index::with_policy(Tiered::new(3)));
+		check_maintenance(SortedArborIndex::new(), SortedArborIndex::new());
+		check_maintenance(HashArborIndex::<_, _, RandomState>::new(), HashArborIndex::new());
+	}
+}
@@ -5,6 +5,7 @@ use rand::{Rng, SeedableRng, StdRng};
 
 use trie::arbor::Arbor;
 use trie::trie::TrieLayer;
+use trie::column::ColumnLayer;
 // use trie::merge::CursorMerger;
 
 fn main() {
@@ -15,6 +16,12 @@ fn main() {
     let waves: u64 = ::std::env::args().nth(4).unwrap().parse().unwrap();
 
     let mut trace = Arbor::<TrieLayer<u64, TrieLayer<u64, Vec<(u64, isize)>>>>::new();
+    let mut column = Arbor::<ColumnLayer<u64, ColumnLayer<u64, Vec<(u64, isize)>, u32>, u32>>::new();
+
+    // arbors of only the even keys, so that odd keys are absent from within their range.
+    let mut sparse = Arbor::<TrieLayer<u64, TrieLayer<u64, Vec<(u64, isize)>>>>::new();
+    let mut filtered = Arbor::<TrieLayer<u64, TrieLayer<u64, Vec<(u64, isize)>>>>::new();
+    filtered.enable_filters(0.01);
 
     let mut counter = 0;
     let mut buffer = Vec::new();
@@ -28,8 +35,10 @@ fn main() {
             }
 
             counter += batch;
-            buffer.sort();
-            trace.extend_ordered(buffer.drain(..));
+            column.extend_unordered(buffer.iter().cloned());
+            sparse.extend_unordered(buffer.iter().map(|&(key, val)| (2 * key, val)));
+            filtered.extend_unordered(buffer.iter().map(|&(key, val)| (2 * key, val)));
+            trace.extend_unordered(buffer.drain(..));
         }
 
         let seed: &[_] = &[1, 2, 3, 4];
@@ -47,6 +56,23 @@ fn main() {
         let mut count = 0;
 
         let mut cursor = trace.cursor();
+        for query in queries.iter() {
+            cursor.seek(query);
+            if cursor.peek().map(|x| x == query).unwrap_or(false) { 
+                count += 1; 
+            }
+        }
+
+        let elapsed = timer.elapsed();
+        let rate = (batch as f64) / (elapsed.as_secs() as f64 + (elapsed.subsec_nanos() as f64 / 1000000000.0f64));
+        println!("throughput: {:.*} lookups/sec @ {:?} elts ({})", 2, rate, (round + 1) * waves * batch, count);
+
+        // the same sorted queries, against columnar tries.
+        let timer = ::std::time::Instant::now();
+
+        let mut count = 0;
+
+        let mut cursor = column.cursor();
         for query in queries.drain(..) {
             cursor.seek(&query);
             if cursor.peek().map(|x| x == &query).unwrap_or(false) { 
@@ -56,6 +82,52 @@ fn main() {
 
         let elapsed = timer.elapsed();
         let rate = (batch as f64) / (elapsed.as_secs() as f64 + (elapsed.subsec_nanos() as f64 / 1000000000.0f64));
-        println!("throughput: {:.*} lookups/sec @ {:?} elts ({})", 2, rate, (round + 1) * waves * batch, count);
+        println!("column throughput: {:.*} lookups/sec @ {:?} elts ({})", 2, rate, (round + 1) * waves * batch, count);
+
+        for _ in 0 .. batch {
+            queries.push(rng.gen_range(0, keys));
+        }
+
+        let timer = ::std::time::Instant::now();
+
+        let mut count = 0;
+        for query in queries.drain(..) {
+            if trace.get(&query).peek().is_some() {
+                count += 1;
+            }
+        }
+
+        let elapsed = timer.elapsed();
+        let rate = (batch as f64) / (elapsed.as_secs() as f64 + (elapsed.subsec_nanos() as f64 / 1000000000.0f64));
+        println!("get throughput: {:.*} lookups/sec @ {:?} elts ({})", 2, rate, (round + 1) * waves * batch, count);
+
+        // queries of which about half are absent, with and without filters.
+        let misses: Vec<u64> = (0 .. batch).map(|_| rng.gen_range(0, 2 * keys)).collect();
+
+        let timer = ::std::time::Instant::now();
+
+        let mut count = 0;
+        for query in misses.iter() {
+            if sparse.get(query).peek().is_some() {
+                count += 1;
+            }
+        }
+
+        let elapsed = timer.elapsed();
+        let rate = (batch as f64) / (elapsed.as_secs() as f64 + (elapsed.subsec_nanos() as f64 / 1000000000.0f64));
+        println!("get (misses) throughput: {:.*} lookups/sec @ {:?} elts ({})", 2, rate, (round + 1) * waves * batch, count);
+
+        let timer = ::std::time::Instant::now();
+
+        let mut count = 0;
+        for query in misses.iter() {
+            if filtered.get(query).peek().is_some() {
+                count += 1;
+            }
+        }
+
+        let elapsed = timer.elapsed();
+        let rate = (batch as f64) / (elapsed.as_secs() as f64 + (elapsed.subsec_nanos() as f64 / 1000000000.0f64));
+        println!("filtered get (misses) throughput: {:.*} lookups/sec @ {:?} elts ({})", 2, rate, (round + 1) * waves * batch, count);
     }
 }
\ No newline at end of file
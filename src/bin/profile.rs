@@ -6,7 +6,9 @@ use rand::{thread_rng, Rng};
 use trie::arbor::Arbor;
 use trie::arbor_index::ArborIndex;
 use trie::trie::TrieLayer;
+use trie::TrieStorage;
 use trie::merge::CursorMerger;
+use trie::heap::{HeapMerger, AdaptiveMerger};
 
 fn main() {
 
@@ -23,6 +25,7 @@ fn main() {
 
         test_arbor(nodes, degree, batch);
         test_index(nodes, degree, batch);
+        test_index_maintenance(nodes, degree, batch);
     }
 }
 
@@ -78,6 +81,29 @@ fn test_arbor(nodes: usize, degree: usize, batch: usize) {
     assert_eq!(count, nodes * degree);
     println!("\tforward fullscan: {:?}", timer.elapsed());
 
+    let mut count = 0;
+    let mut merger = HeapMerger::new();
+    let mut cursor = arbor_forward.cursor();
+    while let Some(batch) = cursor.next() {
+        merger.refill_from(batch);
+        while let Some(inner) = merger.next() {
+            count += inner.len();
+        }
+    }
+    assert_eq!(count, nodes * degree);
+    println!("\tforward fullscan (heap): {:?}", timer.elapsed());
+
+    let mut count = 0;
+    let mut cursor = arbor_forward.cursor();
+    while let Some(batch) = cursor.next() {
+        let mut merger = AdaptiveMerger::from(batch);
+        while let Some(inner) = merger.next() {
+            count += inner.len();
+        }
+    }
+    assert_eq!(count, nodes * degree);
+    println!("\tforward fullscan (adaptive): {:?}", timer.elapsed());
+
     let mut merger = CursorMerger::new();
     let mut cursor = arbor_reverse.cursor();
     while let Some(batch) = cursor.next() {
@@ -96,6 +122,29 @@ fn test_arbor(nodes: usize, degree: usize, batch: usize) {
     }
     assert_eq!(count, nodes * degree);
     println!("\treverse fullscan: {:?}", timer.elapsed());
+
+    let mut count = 0;
+    for node in 0 .. nodes as u32 {
+        let mut merger = arbor_forward.get(&node);
+        while let Some(inner) = merger.next() {
+            count += inner.len();
+        }
+    }
+    assert_eq!(count, nodes * degree);
+    println!("\tforward lookups: {:?}", timer.elapsed());
+
+    let keys: Vec<u32> = (0 .. nodes as u32).collect();
+
+    let mut count = 0;
+    for chunk in keys.chunks(batch) {
+        for mut merger in arbor_forward.get_many(chunk) {
+            while let Some(inner) = merger.next() {
+                count += inner.len();
+            }
+        }
+    }
+    assert_eq!(count, nodes * degree);
+    println!("\tforward lookups (batched): {:?}", timer.elapsed());
 }
 
 #[inline(never)]
@@ -169,24 +218,85 @@ fn test_index(nodes: usize, degree: usize, batch: usize) {
     assert_eq!(count, nodes * degree);
     println!("\treverse fullscan: {:?}", timer.elapsed());
 
-    // let mut count = 0;
-    // for node in 0 .. nodes as u32 {
-    //     arbor_forward.get_into(&node, &mut merger);
-    //     while let Some(inner) = merger.next() {
-    //         count += inner.len();
-    //     }
-    // }
-    // assert_eq!(count, nodes * degree);
-    // println!("\tforward index: {:?}", timer.elapsed());
-
-    // let mut count = 0;
-    // for node in 0 .. nodes as u32 {
-    //     arbor_reverse.get_into(&node, &mut merger);
-    //     while let Some(inner) = merger.next() {
-    //         count += inner.len();
-    //     }
-    // }
-    // assert_eq!(count, nodes * degree);
-    // println!("\treverse index: {:?}", timer.elapsed());
-
-}
\ No newline at end of file
+    let mut count = 0;
+    let mut merger = CursorMerger::new();
+    for node in 0 .. nodes as u32 {
+        arbor_forward.get_into(&node, &mut merger);
+        while let Some(inner) = merger.next() {
+            count += inner.len();
+        }
+    }
+    assert_eq!(count, nodes * degree);
+    println!("\tforward index: {:?}", timer.elapsed());
+
+    let mut count = 0;
+    let mut merger = CursorMerger::new();
+    for node in 0 .. nodes as u32 {
+        arbor_reverse.get_into(&node, &mut merger);
+        while let Some(inner) = merger.next() {
+            count += inner.len();
+        }
+    }
+    assert_eq!(count, nodes * degree);
+    println!("\treverse index: {:?}", timer.elapsed());
+
+    let keys: Vec<u32> = (0 .. nodes as u32).collect();
+
+    let mut count = 0;
+    for chunk in keys.chunks(batch) {
+        for mut merger in arbor_forward.get_many(chunk) {
+            while let Some(inner) = merger.next() {
+                count += inner.len();
+            }
+        }
+    }
+    assert_eq!(count, nodes * degree);
+    println!("\tforward index (batched): {:?}", timer.elapsed());
+
+}
+
+// compares incremental index maintenance against re-indexing merged tries.
+#[inline(never)]
+fn test_index_maintenance(nodes: usize, degree: usize, batch: usize) {
+
+    println!("test_index_maintenance(nodes: {}, degree: {}, batch: {})", nodes, degree, batch);
+
+    // batches of edges from random nodes, each retracting the edges of the batch four earlier,
+    // so that merges both find keys in several tries and drop keys whose edges cancel.
+    let mut rng = thread_rng();
+    let mut batches: Vec<Vec<(u32, (u32, i32))>> = Vec::new();
+    for round in 0 .. (nodes / batch) {
+        let mut tuples = Vec::new();
+        for _ in 0 .. batch {
+            let node = rng.gen_range(0, nodes);
+            for edge in 0 .. degree {
+                tuples.push((node as u32, (((node + edge) % nodes) as u32, 1)));
+            }
+        }
+        if round >= 4 {
+            tuples.extend(batches[round - 4].iter().filter(|x| (x.1).1 > 0).map(|&(src, (dst, _))| (src, (dst, -1))));
+        }
+        batches.push(tuples);
+    }
+
+    // batches repeat tuples, and are sorted and consolidated before timing.
+    let tries = || batches.iter().map(|tuples| TrieLayer::from_unordered(tuples.iter().cloned())).collect::<Vec<_>>();
+
+    let tries1 = tries();
+    let timer = ::std::time::Instant::now();
+    let mut index = ArborIndex::<u32, Vec<(u32, i32)>>::new();
+    for trie in tries1 {
+        index.append(trie);
+    }
+    println!("\tincremental: {:?}", timer.elapsed());
+    index.validate().unwrap();
+
+    let tries2 = tries();
+    let timer = ::std::time::Instant::now();
+    let mut index = ArborIndex::<u32, Vec<(u32, i32)>>::new();
+    for trie in tries2 {
+        index.append_reindexed(trie);
+    }
+    println!("\treindexed: {:?}", timer.elapsed());
+    index.validate().unwrap();
+}
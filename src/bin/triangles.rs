@@ -0,0 +1,75 @@
+extern crate rand;
+extern crate trie;
+
+use rand::{Rng, SeedableRng, StdRng};
+
+use trie::arbor::Arbor;
+use trie::trie::TrieLayer;
+use trie::set::KeySet;
+use trie::merge::CursorMerger;
+use trie::join::Leapfrog;
+
+fn main() {
+
+    if ::std::env::args().count() != 4 {
+        println!("counts triangles in a random graph using leapfrog intersection");
+        println!("usage: <nodes> <edges> <batch_size>");
+    }
+    else {
+        let nodes: u32 = ::std::env::args().nth(1).unwrap().parse().unwrap();
+        let edges: usize = ::std::env::args().nth(2).unwrap().parse().unwrap();
+        let batch: usize = ::std::env::args().nth(3).unwrap().parse().unwrap();
+
+        println!("running with nodes: {}, edges: {}, batch: {}", nodes, edges, batch);
+
+        let timer = ::std::time::Instant::now();
+
+        let seed: &[_] = &[1, 2, 3, 4];
+        let mut rng: StdRng = SeedableRng::from_seed(seed);
+
+        // edges are oriented from smaller to larger identifier, so that each triangle is found once.
+        let mut graph = Arbor::<TrieLayer<u32, KeySet<u32>>>::new();
+        let mut buffer = Vec::with_capacity(batch);
+        for edge in 0 .. edges {
+            let src = rng.gen_range(0, nodes);
+            let dst = rng.gen_range(0, nodes);
+            if src != dst {
+                buffer.push((::std::cmp::min(src, dst), ::std::cmp::max(src, dst)));
+            }
+
+            if edge % batch == (batch - 1) {
+                graph.extend_unordered_radix(buffer.drain(..));
+            }
+        }
+        graph.extend_unordered_radix(buffer.drain(..));
+
+        println!("\tloading elapsed: {:?}", timer.elapsed());
+
+        let mut count = 0;
+        let mut vals = Vec::new();
+        let mut cursor = graph.cursor();
+        while let Some(view) = cursor.next() {
+
+            let neighbors = view.collect::<Vec<_>>();
+
+            // for each neighbor `b` of `a`, intersect the neighbors of `a` and `b`.
+            let mut middle = CursorMerger::from(neighbors.iter().cloned());
+            while let Some(b) = middle.next().map(|view| view.key().unwrap()) {
+                let mut probe = graph.cursor();
+                probe.seek(b);
+                if probe.peek() == Some(b) {
+                    let others = probe.next().unwrap().collect::<Vec<_>>();
+                    let mut join = Leapfrog::new(vec![
+                        CursorMerger::from(neighbors.iter().cloned()),
+                        CursorMerger::from(others.into_iter()),
+                    ]);
+                    while join.next_into(&mut vals).is_some() {
+                        count += 1;
+                    }
+                }
+            }
+        }
+
+        println!("\ttriangles: {} elapsed: {:?}", count, timer.elapsed());
+    }
+}
@@ -0,0 +1,215 @@
+//! Bloom filters, for skipping tries that cannot contain a key.
+//!
+//! A `Bloom` filter records each inserted key by setting several bits of a bit vector,
+//! chosen by hashing the key. A key whose bits are not all set was certainly not inserted,
+//! whereas a key whose bits are all set probably was, though its bits may have been set
+//! by other keys. The fraction of absent keys mistaken for present keys, the filter's
+//! false-positive rate, falls exponentially in the number of bits per key.
+//!
+//! The bits of each key lie within one block of 512 bits, a typical cache line, so that 
+//! checking a key touches one line of memory however many bits it checks. This raises
+//! the false-positive rate slightly above that of an unblocked filter of the same size,
+//! and `Density::for_rate` makes up for it with a few more bits for each key.
+//!
+//! Filters are built from 64-bit hashes of keys rather than from keys, and mix each hash
+//! before use, so that even weak hashes of small integer keys spread across the bits.
+
+use std::hash::{Hash, Hasher};
+
+use fnv::FnvHasher;
+
+/// The numbers of bits and of hashes for each key inserted into a `Bloom` filter.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Density {
+	pub bits: usize,
+	pub hashes: usize,
+}
+
+impl Density {
+	/// The density using the fewest bits for a false-positive `rate` strictly between zero and one.
+	///
+	/// Keys spread unevenly across blocks, and crowded blocks mistake more absent keys, so
+	/// that the density has a few more bits than an unblocked filter would need. Rates
+	/// too small to reach with `MAX_BITS` bits for each key are given that many bits.
+	pub fn for_rate(rate: f64) -> Density {
+		assert!(rate > 0.0 && rate < 1.0, "false-positive rate must be between zero and one");
+		let ln2 = ::std::f64::consts::LN_2;
+		let mut bits = ::std::cmp::min((-rate.ln() / (ln2 * ln2)).ceil() as usize, MAX_BITS);
+		loop {
+			// fewer hashes than an unblocked filter would use suit more crowded blocks.
+			let most = ::std::cmp::max(1, (bits as f64 * ln2).round() as usize);
+			let density = (1 .. most + 1).map(|hashes| Density { bits: bits, hashes: hashes })
+				.min_by(|x, y| x.rate().partial_cmp(&y.rate()).unwrap())
+				.unwrap();
+			if density.rate() <= rate || bits >= MAX_BITS {
+				return density;
+			}
+			bits += 1;
+		}
+	}
+
+	/// The expected false-positive rate of a filter of this density, once full.
+	pub fn rate(&self) -> f64 {
+		// the number of keys in each block is about Poisson distributed, with mean `keys`.
+		let keys = 512.0 / self.bits as f64;
+		let limit = (keys + 12.0 * keys.sqrt() + 12.0) as usize;
+		let mut log_probability = -keys;
+		let mut rate = 0.0;
+		for count in 0 .. limit {
+			// each probed bit is set unless all of the `count * hashes` bits set missed it.
+			let unset = (1.0 - 1.0 / 512.0f64).powf((count * self.hashes) as f64);
+			rate += log_probability.exp() * (1.0 - unset).powf(self.hashes as f64);
+			log_probability += keys.ln() - ((count + 1) as f64).ln();
+		}
+		rate
+	}
+}
+
+// the number of 64-bit words in each block of a filter.
+const BLOCK_WORDS: usize = 8;
+
+/// The most bits for each key of any density `Density::for_rate` produces, a block each.
+pub const MAX_BITS: usize = 512;
+
+/// A set of hashes, which may report hashes as present that were never inserted.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Bloom {
+	words: Vec<u64>,
+	hashes: usize,
+}
+
+impl Bloom {
+	/// Allocates an empty filter for `keys` keys at `density`.
+	pub fn new(keys: usize, density: Density) -> Bloom {
+		let blocks = ::std::cmp::max(1, (keys.saturating_mul(density.bits) + 511) / 512);
+		Bloom {
+			words: vec![0; blocks * BLOCK_WORDS],
+			hashes: density.hashes,
+		}
+	}
+
+	/// Inserts `hash` into the filter.
+	pub fn insert(&mut self, hash: u64) {
+		let (block, mut probes) = self.probes(hash);
+		for _ in 0 .. self.hashes {
+			let bit = probes.next();
+			self.words[block + bit / 64] |= 1 << (bit % 64);
+		}
+	}
+
+	/// Returns false if `hash` was certainly not inserted, and true if it may have been.
+	pub fn contains(&self, hash: u64) -> bool {
+		let (block, mut probes) = self.probes(hash);
+		for _ in 0 .. self.hashes {
+			let bit = probes.next();
+			if self.words[block + bit / 64] & (1 << (bit % 64)) == 0 {
+				return false;
+			}
+		}
+		true
+	}
+
+	// the first word of the block for `hash`, and the bits to probe within the block.
+	fn probes(&self, hash: u64) -> (usize, Probes) {
+		let mixed = mix(hash);
+		let blocks = self.words.len() / BLOCK_WORDS;
+		let block = (mixed % blocks as u64) as usize * BLOCK_WORDS;
+		(block, Probes { state: mixed, bits: 0, unused: 0 })
+	}
+
+	/// Reports the number of bytes the filter has allocated.
+	pub fn memory_usage(&self) -> usize {
+		self.words.capacity() * 8
+	}
+}
+
+/// Hashes `key` for use with a `Bloom` filter.
+pub fn hash<K: Hash>(key: &K) -> u64 {
+	let mut hasher = FnvHasher::default();
+	key.hash(&mut hasher);
+	hasher.finish()
+}
+
+// The bits of a block to probe for a hash, nine bits of a splitmix64 stream at a time.
+//
+// probes drawn independently, rather than as an arithmetic progression from the hash, keep
+// keys that share one probe from sharing others, which would raise the false-positive rate.
+struct Probes {
+	state: u64,
+	bits: u64,
+	unused: usize,
+}
+
+impl Probes {
+	fn next(&mut self) -> usize {
+		if self.unused < 9 {
+			self.state = self.state.wrapping_add(0x9e3779b97f4a7c15);
+			self.bits = mix(self.state);
+			self.unused = 64;
+		}
+		let bit = (self.bits & 511) as usize;
+		self.bits >>= 9;
+		self.unused -= 9;
+		bit
+	}
+}
+
+// the finalizer of splitmix64, so that every bit of `hash` affects every bit of the result.
+fn mix(hash: u64) -> u64 {
+	let mut mixed = hash;
+	mixed = (mixed ^ (mixed >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+	mixed = (mixed ^ (mixed >> 27)).wrapping_mul(0x94d049bb133111eb);
+	mixed ^ (mixed >> 31)
+}
+
+#[cfg(test)]
+mod tests {
+
+	use super::{Bloom, Density, MAX_BITS, hash};
+
+	#[test]
+	fn densities() {
+		assert_eq!(Density::for_rate(0.01), Density { bits: 10, hashes: 7 });
+		assert_eq!(Density::for_rate(0.001), Density { bits: 16, hashes: 10 });
+		for &rate in &[0.5, 0.1, 0.01, 0.001, 1e-6, 1e-12] {
+			let density = Density::for_rate(rate);
+			assert!(density.rate() <= rate && density.hashes <= density.bits);
+			// one bit fewer would not reach the rate.
+			let fewer = (1 .. density.bits).map(|hashes| Density { bits: density.bits - 1, hashes: hashes });
+			assert!(fewer.map(|density| density.rate()).all(|fewer| fewer > rate));
+		}
+		assert_eq!(Density::for_rate(1e-300).bits, MAX_BITS);
+	}
+
+	#[test]
+	fn no_false_negatives() {
+		for &keys in &[0, 1, 10, 1000, 20000] {
+			let mut filter = Bloom::new(keys, Density::for_rate(0.01));
+			for key in 0 .. keys as u64 {
+				filter.insert(hash(&key));
+			}
+			assert!((0 .. keys as u64).all(|key| filter.contains(hash(&key))));
+		}
+		// a filter sized for fewer keys than it holds degrades, but still finds them all.
+		let mut filter = Bloom::new(10, Density::for_rate(0.01));
+		for key in 0 .. 1000u64 {
+			filter.insert(hash(&key));
+		}
+		assert!((0 .. 1000u64).all(|key| filter.contains(hash(&key))));
+	}
+
+	#[test]
+	fn false_positive_rates() {
+		// the measured rates are within sampling error of the rates the densities are for.
+		for &rate in &[0.1, 0.05, 0.01, 0.001] {
+			let mut filter = Bloom::new(20000, Density::for_rate(rate));
+			for key in 0 .. 20000u64 {
+				filter.insert(hash(&key));
+			}
+			let absent = 20000 .. 220000u64;
+			let positives = absent.clone().filter(|key| filter.contains(hash(key))).count();
+			let measured = positives as f64 / (absent.end - absent.start) as f64;
+			assert!(measured < 1.25 * rate, "rate {} measured as {}", rate, measured);
+		}
+	}
+}
@@ -0,0 +1,432 @@
+//! A trie layer storing its keys and offsets in separate columns.
+//!
+//! A `TrieLayer` interleaves each key with the offset at which its values end, which
+//! pads small keys out to the alignment of `usize` and means that searching the keys
+//! also moves their offsets through the cache. A `ColumnLayer` holds the same data as
+//! a `TrieLayer` in two vectors, and can store its offsets in narrower types when the
+//! layer beneath it is small enough. Should the layer beneath outgrow the narrow type,
+//! the offsets beyond it are stored as `usize` rather than truncated.
+
+use {TrieStorage, TrieRef};
+use trie::{Cursor, BackwardCursor, Retract, Violation, validate_order, validate_offsets, advance, retreat, fueled_step};
+
+/// An unsigned integer type used to record offsets into the layer beneath.
+pub trait Offset : Copy+Ord {
+	/// Converts an offset from a `usize`, or returns `None` if it does not fit.
+	fn from_usize(offset: usize) -> Option<Self>;
+	/// Converts the offset to a `usize`.
+	fn to_usize(self) -> usize;
+}
+
+macro_rules! implement_offset {
+	($($t:ty),*) => {
+		$(
+			impl Offset for $t {
+				#[inline(always)] fn from_usize(offset: usize) -> Option<Self> {
+					if offset as u64 <= <$t>::MAX as u64 { Some(offset as $t) } else { None }
+				}
+				#[inline(always)] fn to_usize(self) -> usize { self as usize }
+			}
+		)*
+	}
+}
+
+implement_offset!(u32, u64, usize);
+
+/// A column of offsets, held as `O` while they fit and as `usize` once they do not.
+///
+/// Offsets do not decrease, so those that fit in `O` form a prefix of the column and
+/// the rest a suffix, and finding an offset compares its index to the prefix length.
+#[derive(Clone, Debug)]
+pub struct Offsets<O> {
+	narrow: Vec<O>,
+	wide: Vec<usize>,
+}
+
+impl<O: Offset> Offsets<O> {
+	/// Allocates an empty column.
+	pub fn new() -> Self { Offsets { narrow: Vec::new(), wide: Vec::new() } }
+	/// Allocates an empty column with space for `capacity` narrow offsets.
+	pub fn with_capacity(capacity: usize) -> Self {
+		Offsets { narrow: Vec::with_capacity(capacity), wide: Vec::new() }
+	}
+	/// Reports the number of offsets.
+	pub fn len(&self) -> usize { self.narrow.len() + self.wide.len() }
+	/// Indicates whether the column holds no offsets.
+	pub fn is_empty(&self) -> bool { self.len() == 0 }
+	/// Reserves space for `additional` more offsets.
+	pub fn reserve(&mut self, additional: usize) {
+		if self.wide.is_empty() { self.narrow.reserve(additional); } else { self.wide.reserve(additional); }
+	}
+	/// Returns the offset at `index`.
+	#[inline(always)]
+	pub fn get(&self, index: usize) -> usize { self.as_slice().get(index) }
+	/// Appends `offset`, which must be at least the last offset.
+	#[inline(always)]
+	pub fn push(&mut self, offset: usize) {
+		if self.wide.is_empty() {
+			if let Some(offset) = O::from_usize(offset) {
+				self.narrow.push(offset);
+				return;
+			}
+		}
+		self.wide.push(offset);
+	}
+	/// Replaces the last offset with `offset`, which must be at least the one before it.
+	pub fn set_last(&mut self, offset: usize) {
+		if self.wide.pop().is_none() {
+			self.narrow.pop();
+		}
+		self.push(offset);
+	}
+	/// Appends the offsets of `other` from `lower` to `upper`, moved from `other_basis` to `self_basis`.
+	#[inline(always)]
+	pub fn extend_rebased(&mut self, other: &Offsets<O>, lower: usize, upper: usize, other_basis: usize, self_basis: usize) {
+		// all offsets fit if the last one does.
+		if self.wide.is_empty() && upper <= other.narrow.len() && O::from_usize((other.narrow[upper - 1].to_usize() + self_basis) - other_basis).is_some() {
+			self.narrow.extend(other.narrow[lower .. upper].iter().map(|&o| O::from_usize((o.to_usize() + self_basis) - other_basis).unwrap()));
+		}
+		else {
+			let other = other.as_slice();
+			for index in lower .. upper {
+				self.push((other.get(index) + self_basis) - other_basis);
+			}
+		}
+	}
+	/// The offsets, as a slice.
+	#[inline(always)]
+	pub fn as_slice<'a>(&'a self) -> OffsetSlice<'a, O> {
+		OffsetSlice::new(&self.narrow[..], &self.wide[..])
+	}
+}
+
+impl<O: Offset> Default for Offsets<O> {
+	fn default() -> Self { Offsets::new() }
+}
+
+/// A borrowed column of offsets, with a prefix of narrow offsets and a suffix of wide ones.
+#[derive(Debug)]
+pub struct OffsetSlice<'a, O: 'a> {
+	narrow: &'a [O],
+	wide: &'a [usize],
+}
+
+impl<'a, O: Offset> OffsetSlice<'a, O> {
+	/// Borrows the offsets `narrow` followed by the offsets `wide`.
+	#[inline(always)]
+	pub fn new(narrow: &'a [O], wide: &'a [usize]) -> Self {
+		OffsetSlice { narrow: narrow, wide: wide }
+	}
+	/// Reports the number of offsets.
+	#[inline(always)]
+	pub fn len(&self) -> usize { self.narrow.len() + self.wide.len() }
+	/// Indicates whether the slice holds no offsets.
+	pub fn is_empty(&self) -> bool { self.len() == 0 }
+	/// Returns the offset at `index`.
+	#[inline(always)]
+	pub fn get(&self, index: usize) -> usize {
+		match self.narrow.get(index) {
+			Some(offset) => offset.to_usize(),
+			None => self.wide[index - self.narrow.len()],
+		}
+	}
+	/// Returns the offsets before and at `index`, where the offset before the first is zero.
+	#[inline(always)]
+	pub fn bounds(&self, index: usize) -> (usize, usize) {
+		if index < self.narrow.len() {
+			let lower = if index == 0 { 0 } else { self.narrow[index-1].to_usize() };
+			(lower, self.narrow[index].to_usize())
+		}
+		else {
+			let lower = if index == 0 { 0 } else { self.get(index-1) };
+			(lower, self.get(index))
+		}
+	}
+	/// Returns the first `upper` offsets.
+	#[inline(always)]
+	pub fn prefix(&self, upper: usize) -> Self {
+		if upper <= self.narrow.len() {
+			OffsetSlice { narrow: &self.narrow[.. upper], wide: &self.wide[.. 0] }
+		}
+		else {
+			OffsetSlice { narrow: self.narrow, wide: &self.wide[.. upper - self.narrow.len()] }
+		}
+	}
+}
+
+impl<'a, O: 'a> Clone for OffsetSlice<'a, O> {
+	fn clone(&self) -> Self { *self }
+}
+
+impl<'a, O: 'a> Copy for OffsetSlice<'a, O> { }
+
+/// A layer of a trie wrapped around another trie, with keys and offsets in separate columns.
+///
+/// The value range of the key at position `i` ends at `offs.get(i)`, and starts either at
+/// zero or at the end of the range of the immediately preceding key.
+#[derive(Debug)]
+pub struct ColumnLayer<K: Ord, L, O=usize> {
+	pub keys: Vec<K>,
+	pub offs: Offsets<O>,
+	pub vals: L,
+}
+
+impl<K: Ord, L, O: Offset> ColumnLayer<K, L, O> {
+	// the range of values associated with the key at `index`.
+	#[inline(always)]
+	fn bounds(&self, index: usize) -> (usize, usize) {
+		self.offs.as_slice().bounds(index)
+	}
+}
+
+impl<K: Ord+Clone, L: TrieStorage, O: Offset> TrieStorage for ColumnLayer<K, L, O> {
+	type Item = (K, L::Item);
+	type Key = K;
+	fn new() -> Self { ColumnLayer { keys: vec![], offs: Offsets::new(), vals: L::new() } }
+	fn with_capacity(other1: &Self, other2: &Self) -> Self {
+		ColumnLayer {
+			keys: Vec::with_capacity(other1.keys.len() + other2.keys.len()),
+			offs: Offsets::with_capacity(other1.offs.len() + other2.offs.len()),
+			vals: L::with_capacity(&other1.vals, &other2.vals),
+		}
+	}
+	fn keys(&self) -> usize { self.keys.len() }
+	fn tuples(&self) -> usize { self.vals.tuples() }
+	fn tuples_in(&self, lower: usize, upper: usize) -> usize {
+		if lower < upper { self.vals.tuples_in(self.bounds(lower).0, self.bounds(upper - 1).1) } else { 0 }
+	}
+	fn key(&self, index: usize) -> &K { &self.keys[index] }
+	fn extend_trie(&mut self, other: &Self, lower: usize, upper: usize) {
+
+		assert!(lower < upper);
+
+		// keys are copied as is, and offsets re-based to the end of our values.
+		let other_basis = other.bounds(lower).0;
+		let self_basis = self.vals.keys();
+		self.keys.extend_from_slice(&other.keys[lower .. upper]);
+		self.offs.reserve(upper - lower);
+		self.offs.extend_rebased(&other.offs, lower, upper, other_basis, self_basis);
+		self.vals.extend_trie(&other.vals, other_basis, other.offs.get(upper-1));
+
+		assert!(self.vals.keys() == self.offs.get(self.offs.len()-1));
+	}
+	fn extend_merge_fueled(&mut self, other1: (&Self, &mut usize, usize), other2: (&Self, &mut usize, usize), fuel: &mut usize) {
+		let (trie1, lower1, upper1) = other1;
+		let (trie2, lower2, upper2) = other2;
+
+		self.keys.reserve(upper1 + upper2 - *lower1 - *lower2);
+		self.offs.reserve(upper1 + upper2 - *lower1 - *lower2);
+
+		while *lower1 < upper1 && *lower2 < upper2 && *fuel > 0 {
+			match trie1.keys[*lower1].cmp(&trie2.keys[*lower2]) {
+				::std::cmp::Ordering::Less => {
+					let step = 1 + advance(&trie1.keys[(1 + *lower1)..upper1], |x| x < &trie2.keys[*lower2]);
+					let (step, tuples) = fueled_step(trie1, *lower1, step, *fuel);
+					self.extend_trie(trie1, *lower1, *lower1 + step);
+					*lower1 += step;
+					*fuel = fuel.saturating_sub(tuples);
+				}
+				::std::cmp::Ordering::Equal => {
+					let (v_lower1, v_upper1) = trie1.bounds(*lower1);
+					let (v_lower2, v_upper2) = trie2.bounds(*lower2);
+
+					let v_len = self.vals.keys();
+					self.vals.extend_merge(
+						(&trie1.vals, v_lower1, v_upper1),
+						(&trie2.vals, v_lower2, v_upper2)
+					);
+					if self.vals.keys() > v_len {
+						self.keys.push(trie1.keys[*lower1].clone());
+						self.offs.push(self.vals.keys());
+					}
+
+					*fuel = fuel.saturating_sub(trie1.tuples_in(*lower1, *lower1 + 1) + trie2.tuples_in(*lower2, *lower2 + 1));
+					*lower1 += 1;
+					*lower2 += 1;
+				}
+				::std::cmp::Ordering::Greater => {
+					let step = 1 + advance(&trie2.keys[(1 + *lower2)..upper2], |x| x < &trie1.keys[*lower1]);
+					let (step, tuples) = fueled_step(trie2, *lower2, step, *fuel);
+					self.extend_trie(trie2, *lower2, *lower2 + step);
+					*lower2 += step;
+					*fuel = fuel.saturating_sub(tuples);
+				}
+			}
+		}
+	}
+	fn extend_merge_equal(&mut self, heads: &[(&Self, usize)]) {
+		let ranges: Vec<_> = heads.iter().map(|&(trie, index)| {
+			let (lower, upper) = trie.bounds(index);
+			(&trie.vals, lower, upper)
+		}).collect();
+		let v_len = self.vals.keys();
+		self.vals.extend_merge_many(&ranges[..]);
+		if self.vals.keys() > v_len {
+			self.keys.push(heads[0].0.keys[heads[0].1].clone());
+			self.offs.push(self.vals.keys());
+		}
+	}
+	fn extend_tuple(&mut self, tuple: (K, L::Item), is_new: bool) {
+		debug_assert!(is_new || self.keys.last().map(|x| x <= &tuple.0).unwrap_or(true), "tuples pushed out of order");
+		let is_new = if is_new || self.keys.last() != Some(&tuple.0) {
+			self.keys.push(tuple.0);
+			self.offs.push(0);
+			true
+		}
+		else {
+			false
+		};
+		self.vals.extend_tuple(tuple.1, is_new);
+		let offset = self.vals.keys();
+		self.offs.set_last(offset);
+	}
+	fn validate_layer(&self, ends: &[usize], depth: usize) -> Result<(), Violation> {
+		if self.offs.len() != self.keys.len() {
+			return Err(Violation::Offset { depth: depth, index: ::std::cmp::min(self.offs.len(), self.keys.len()) });
+		}
+		validate_order(&self.keys[..], |x| x, ends, depth)?;
+		let ends = validate_offsets((0 .. self.offs.len()).map(|i| self.offs.get(i)), self.vals.keys(), depth)?;
+		self.vals.validate_layer(&ends[..], depth + 1)
+	}
+	fn cmp_items(item1: &Self::Item, item2: &Self::Item) -> ::std::cmp::Ordering {
+		item1.0.cmp(&item2.0).then_with(|| L::cmp_items(&item1.1, &item2.1))
+	}
+	fn add_items(item1: &mut Self::Item, item2: Self::Item) {
+		L::add_items(&mut item1.1, item2.1);
+	}
+	fn is_zero_item(item: &Self::Item) -> bool {
+		L::is_zero_item(&item.1)
+	}
+	fn item_key(item: &Self::Item) -> &K { &item.0 }
+}
+
+impl<K: Ord+Clone, L: Retract, O: Offset> Retract for ColumnLayer<K, L, O> {
+	fn negate_item(item: &mut Self::Item) {
+		L::negate_item(&mut item.1);
+	}
+}
+
+impl<'a, K: Ord+'a, L: 'a, O: Offset+'a> TrieRef<'a> for ColumnLayer<K, L, O> where L: TrieRef<'a> {
+	type Cursor = ColumnCursor<'a, K, O, L>;
+	fn keys_cnt(&self) -> usize { self.keys.len() }
+	fn cursor(&'a self, lower: usize, upper: usize) -> Self::Cursor {
+		let mut cursor = ColumnCursor::new(&self.keys[.. upper], self.offs.as_slice().prefix(upper), &self.vals);
+		cursor.index = lower;
+		cursor
+	}
+}
+
+/// A cursor over columns of keys and offsets.
+///
+/// As with a `TrieCursor`, the columns are held from the start of the layer so that
+/// the value range of the key at `index` can always be found.
+pub struct ColumnCursor<'a, K: 'a, O: 'a, L: 'a> {
+	pub index: usize,
+	pub keys: &'a [K],
+	pub offs: OffsetSlice<'a, O>,
+	pub vals: &'a L,
+}
+
+impl<'a, K: Ord+'a, O: Offset, L: TrieRef<'a>> ColumnCursor<'a, K, O, L> {
+	pub fn new(keys: &'a [K], offs: OffsetSlice<'a, O>, vals: &'a L) -> ColumnCursor<'a, K, O, L> {
+		ColumnCursor {
+			index: 0,
+			keys: keys,
+			offs: offs,
+			vals: vals,
+		}
+	}
+
+	// the key at `index`, and a cursor over its values.
+	#[inline(always)]
+	fn item(&self, index: usize) -> (&'a K, L::Cursor) {
+		let (v_lower, v_upper) = self.offs.bounds(index);
+		(&self.keys[index], self.vals.cursor(v_lower, v_upper))
+	}
+}
+
+impl<'a, K: Ord+'a, O: Offset, L: TrieRef<'a>> Cursor<'a> for ColumnCursor<'a, K, O, L> {
+
+	type Key = K;
+	type Val = L::Cursor;
+
+	fn next(&mut self) -> Option<(&'a K, L::Cursor)> {
+		if self.index < self.keys.len() {
+			self.index += 1;
+			Some(self.item(self.index - 1))
+		}
+		else {
+			None
+		}
+	}
+
+	#[inline(never)]
+	fn seek(&mut self, key: &K) {
+		self.index += advance(&self.keys[self.index ..], |x| x < key);
+	}
+	fn peek(&self) -> Option<&'a K> {
+		if self.index < self.keys.len() { Some(&self.keys[self.index]) } else { None }
+	}
+	fn size(&self) -> usize {
+		self.keys.len() - self.index
+	}
+}
+
+impl<'a, K: Ord+'a, O: Offset, L: TrieRef<'a>> BackwardCursor<'a> for ColumnCursor<'a, K, O, L> {
+	fn prev(&mut self) -> Option<(&'a K, L::Cursor)> {
+		if self.index < self.keys.len() {
+			let result = self.item(self.keys.len() - 1);
+			self.keys = &self.keys[.. self.keys.len() - 1];
+			self.offs = self.offs.prefix(self.offs.len() - 1);
+			Some(result)
+		}
+		else {
+			None
+		}
+	}
+
+	#[inline(never)]
+	fn seek_back(&mut self, key: &K) {
+		let count = retreat(&self.keys[self.index ..], |x| x > key);
+		self.keys = &self.keys[.. self.keys.len() - count];
+		self.offs = self.offs.prefix(self.offs.len() - count);
+	}
+	fn peek_back(&self) -> Option<&'a K> {
+		if self.index < self.keys.len() { Some(&self.keys[self.keys.len()-1]) } else { None }
+	}
+}
+
+impl<'a, K: 'a, O: 'a, L: 'a> Clone for ColumnCursor<'a, K, O, L> {
+	fn clone(&self) -> Self {
+		ColumnCursor {
+			index: self.index,
+			keys: self.keys,
+			offs: self.offs,
+			vals: self.vals,
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+
+	use super::Offsets;
+
+	#[test]
+	fn offsets_widen() {
+		let large = u32::MAX as usize + 1;
+		let mut offs = Offsets::<u32>::new();
+		offs.push(3);
+		offs.push(5);
+		offs.set_last(large);
+		offs.push(large + 1);
+		assert_eq!(offs.len(), 3);
+		assert_eq!((0 .. 3).map(|i| offs.get(i)).collect::<Vec<_>>(), vec![3, large, large + 1]);
+		let prefix = offs.as_slice().prefix(2);
+		assert_eq!((prefix.len(), prefix.get(1)), (2, large));
+		let mut copy = Offsets::<u32>::new();
+		copy.push(1);
+		copy.extend_rebased(&offs, 0, 3, 0, 1);
+		assert_eq!((0 .. 4).map(|i| copy.get(i)).collect::<Vec<_>>(), vec![1, 4, large + 1, large + 2]);
+	}
+}
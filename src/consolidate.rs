@@ -0,0 +1,183 @@
+//! Cursors that consolidate the contents of several tries.
+//!
+//! A `CursorMerger` presents each copy of a key from each merged cursor, even when
+//! the differences of the copies cancel. A `Consolidated` cursor instead combines 
+//! the values of each key: leaf differences are added up, and nested cursors are 
+//! themselves consolidated. Keys whose consolidated values are empty, or whose net
+//! differences are zero, are not presented at all.
+
+use trie::{Cursor, TrieRef, TrieCursor, SliceCursor, Monoid};
+use merge::CursorMerger;
+use column::{ColumnCursor, Offset};
+
+/// Cursors whose values may be consolidated across several copies of a key.
+pub trait Consolidate<'a> : Cursor<'a> {
+	/// The consolidated form of the values of a key.
+	type Output;
+	/// Consolidates values sharing a key, returning `None` if their net weight is zero.
+	fn consolidate<I: Iterator<Item=Self::Val>>(vals: I) -> Option<Self::Output>;
+}
+
+impl<'a, K: Ord+'a, R: Monoid+'a> Consolidate<'a> for SliceCursor<'a, K, R> {
+	type Output = R;
+	fn consolidate<I: Iterator<Item=&'a R>>(mut vals: I) -> Option<R> {
+		if let Some(first) = vals.next() {
+			let mut sum = first.clone();
+			for val in vals {
+				sum.plus_equals(val);
+			}
+			if sum.is_zero() { None } else { Some(sum) }
+		}
+		else {
+			None
+		}
+	}
+}
+
+impl<'a, K: Ord+'a, L: TrieRef<'a>> Consolidate<'a> for TrieCursor<'a, K, L> where L::Cursor: Consolidate<'a> {
+	type Output = Consolidated<'a, L::Cursor>;
+	fn consolidate<I: Iterator<Item=L::Cursor>>(vals: I) -> Option<Self::Output> {
+		let mut result = Consolidated::new(CursorMerger::from(vals));
+		if result.peek().is_some() { Some(result) } else { None }
+	}
+}
+
+impl<'a, K: Ord+'a, O: Offset, L: TrieRef<'a>> Consolidate<'a> for ColumnCursor<'a, K, O, L> where L::Cursor: Consolidate<'a> {
+	type Output = Consolidated<'a, L::Cursor>;
+	fn consolidate<I: Iterator<Item=L::Cursor>>(vals: I) -> Option<Self::Output> {
+		let mut result = Consolidated::new(CursorMerger::from(vals));
+		if result.peek().is_some() { Some(result) } else { None }
+	}
+}
+
+/// A cursor-like consolidation of several cursors.
+///
+/// Keys are consolidated one at a time, as `peek` or `next` asks for them. Consolidating
+/// a key with nested values consolidates only the first key of each layer beneath it, to
+/// learn whether any of its values remain.
+pub struct Consolidated<'a, C: Consolidate<'a>> {
+	merger: CursorMerger<'a, C>,
+	pending: Option<(&'a C::Key, C::Output)>,
+}
+
+impl<'a, C: Consolidate<'a>> Consolidated<'a, C> {
+
+	/// Creates a consolidating cursor from a cursor merger.
+	pub fn new(merger: CursorMerger<'a, C>) -> Consolidated<'a, C> {
+		Consolidated { merger: merger, pending: None }
+	}
+
+	/// Reveals the next key, if one exists, consolidating it if it has not been.
+	pub fn peek(&mut self) -> Option<&'a C::Key> {
+		if self.pending.is_none() {
+			self.fill();
+		}
+		self.pending.as_ref().map(|x| x.0)
+	}
+
+	/// Advances to the first key at least as large as `key`.
+	///
+	/// Keys skipped over are not consolidated.
+	pub fn seek(&mut self, key: &C::Key) {
+		if let Some(pending) = self.pending.as_ref().map(|x| x.0) {
+			if pending >= key { return; }
+			self.pending = None;
+		}
+		self.merger.seek(key);
+	}
+
+	// locates the next key with a non-zero consolidated value.
+	fn fill(&mut self) {
+		while let Some(view) = self.merger.next() {
+			let key = view.key().unwrap();
+			if let Some(output) = C::consolidate(view) {
+				self.pending = Some((key, output));
+				return;
+			}
+		}
+	}
+}
+
+impl<'a, C: Consolidate<'a>> Iterator for Consolidated<'a, C> {
+	type Item = (&'a C::Key, C::Output);
+	fn next(&mut self) -> Option<Self::Item> {
+		if self.pending.is_none() {
+			self.fill();
+		}
+		self.pending.take()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+
+	use std::collections::BTreeMap;
+
+	use TrieStorage;
+	use trie::{TrieRef, TrieLayer};
+	use merge::CursorMerger;
+	use testing::triples;
+	use super::Consolidated;
+
+	type Trie = TrieLayer<u32, TrieLayer<u32, Vec<(u32, i64)>>>;
+
+	// the consolidated tuples of `tries` at least `lower`, through a `Consolidated` cursor.
+	fn consolidated(tries: &[Trie], lower: u32) -> Vec<(u32, u32, u32, i64)> {
+		let mut cursor = Consolidated::new(CursorMerger::from(tries.iter().map(|trie| trie.enumerate())));
+		cursor.seek(&lower);
+		let mut result = Vec::new();
+		for (&key, vals) in cursor {
+			let before = result.len();
+			for (&val, times) in vals {
+				for (&time, diff) in times {
+					assert!(diff != 0);
+					result.push((key, val, time, diff));
+				}
+			}
+			assert!(result.len() > before, "key {} presented without values", key);
+		}
+		result
+	}
+
+	// the non-zero accumulations of the tuples of `tries` at least `lower`.
+	fn expected(tries: &[Vec<(u32, (u32, (u32, i64)))>], lower: u32) -> Vec<(u32, u32, u32, i64)> {
+		let mut model = BTreeMap::new();
+		for &(key, (val, (time, diff))) in tries.iter().flat_map(|tuples| tuples.iter()) {
+			*model.entry((key, val, time)).or_insert(0) += diff;
+		}
+		model.into_iter().filter(|x| x.1 != 0 && (x.0).0 >= lower).map(|((key, val, time), diff)| (key, val, time, diff)).collect()
+	}
+
+	#[test]
+	fn agrees_with_model() {
+		let mut seed = 8;
+		for round in 0 .. 20 {
+			let tuples: Vec<_> = (0 .. 1 + round % 5).map(|_| triples(&mut seed, 10 + 20 * round, 30, 3)).collect();
+			let tries: Vec<Trie> = tuples.iter().map(|tuples| Trie::from_unordered(tuples.clone().into_iter())).collect();
+			for &lower in &[0, 1, 15, 29, 30] {
+				assert_eq!(consolidated(&tries[..], lower), expected(&tuples[..], lower));
+			}
+		}
+	}
+
+	#[test]
+	fn cancelling_tries_produce_nothing() {
+		let mut seed = 9;
+		let tuples = triples(&mut seed, 200, 30, 3);
+		let negated: Vec<_> = tuples.iter().map(|&(key, (val, (time, diff)))| (key, (val, (time, -diff)))).collect();
+		let trie = Trie::from_unordered(tuples.clone().into_iter());
+		let opposite = Trie::from_unordered(negated.clone().into_iter());
+		assert!(trie.keys() > 0);
+
+		let mut cursor = Consolidated::new(CursorMerger::from(vec![trie.enumerate(), opposite.enumerate()].into_iter()));
+		assert!(cursor.peek().is_none());
+		assert!(cursor.next().is_none());
+
+		// the retractions of half the keys leave only the other half.
+		let half: Vec<_> = negated.into_iter().filter(|x| x.0 % 2 == 0).collect();
+		let tries = vec![trie, Trie::from_unordered(half.clone().into_iter())];
+		let found = consolidated(&tries[..], 0);
+		assert!(!found.is_empty() && found.iter().all(|x| x.0 % 2 == 1));
+		assert_eq!(found, expected(&[tuples, half], 0));
+	}
+}
@@ -0,0 +1,203 @@
+//! Iterators over the flattened tuples of nested cursors.
+//!
+//! A cursor over a `TrieLayer<K, TrieLayer<V, Vec<(W, R)>>>` presents each key with a
+//! cursor over its values, which must in turn be navigated to reach the leaves. The
+//! `Tuples` iterator instead presents each path from the root to a leaf as a tuple of
+//! references, here `(&K, (&V, (&W, &R)))`, in order.
+//!
+//! A `CursorMerger` may be flattened as well, presenting the tuples of all of its
+//! cursors in order. Tuples present in several cursors are presented once for each
+//! cursor, with their differences; a `Consolidated` cursor accumulates them instead.
+
+use std::iter::{FromIterator, Once};
+
+use {TrieStorage, TrieRef, CursorMerger};
+use trie::{Cursor, TrieLayer, TrieCursor, SliceCursor};
+use column::{ColumnCursor, Offset};
+
+/// Cursors whose values may be flattened into suffixes of tuples.
+pub trait Flatten<'a> : Cursor<'a> {
+	/// The suffix of a tuple following its key at this level.
+	type Suffix;
+	/// An iterator over the suffixes for one value.
+	type Iter: Iterator<Item=Self::Suffix>;
+	/// An iterator over the suffixes for several values of the same key, in order.
+	type Group: Iterator<Item=Self::Suffix>;
+	/// Produces the suffixes of tuples beneath a value.
+	fn flatten(val: Self::Val) -> Self::Iter;
+	/// Produces the suffixes of tuples beneath several values of the same key.
+	fn group(vals: Vec<Self::Val>) -> Self::Group;
+}
+
+/// An iterator over the flattened tuples of a cursor.
+pub struct Tuples<'a, C: Flatten<'a>> {
+	cursor: C,
+	current: Option<(&'a C::Key, C::Iter)>,
+}
+
+impl<'a, C: Flatten<'a>> Tuples<'a, C> {
+	/// Creates an iterator over the flattened tuples of `cursor`.
+	pub fn new(cursor: C) -> Tuples<'a, C> {
+		Tuples { cursor: cursor, current: None }
+	}
+}
+
+impl<'a, C: Flatten<'a>> Iterator for Tuples<'a, C> {
+	type Item = (&'a C::Key, C::Suffix);
+	fn next(&mut self) -> Option<Self::Item> {
+		loop {
+			if let Some((key, ref mut iter)) = self.current {
+				if let Some(suffix) = iter.next() {
+					return Some((key, suffix));
+				}
+			}
+			match self.cursor.next() {
+				Some((key, val)) => { self.current = Some((key, C::flatten(val))); },
+				None => { self.current = None; return None; },
+			}
+		}
+	}
+}
+
+impl<'a, K: Ord+'a, R: 'a> Flatten<'a> for SliceCursor<'a, K, R> {
+	type Suffix = &'a R;
+	type Iter = Once<&'a R>;
+	type Group = ::std::vec::IntoIter<&'a R>;
+	fn flatten(val: &'a R) -> Self::Iter { ::std::iter::once(val) }
+	fn group(vals: Vec<&'a R>) -> Self::Group { vals.into_iter() }
+}
+
+impl<'a, K: Ord+'a, L: TrieRef<'a>> Flatten<'a> for TrieCursor<'a, K, L> where L::Cursor: Flatten<'a> {
+	type Suffix = (&'a <L::Cursor as Cursor<'a>>::Key, <L::Cursor as Flatten<'a>>::Suffix);
+	type Iter = Tuples<'a, L::Cursor>;
+	type Group = Tuples<'a, CursorMerger<'a, L::Cursor>>;
+	fn flatten(val: L::Cursor) -> Self::Iter { Tuples::new(val) }
+	fn group(vals: Vec<L::Cursor>) -> Self::Group { Tuples::new(CursorMerger::from(vals.into_iter())) }
+}
+
+impl<'a, K: Ord+'a, O: Offset, L: TrieRef<'a>> Flatten<'a> for ColumnCursor<'a, K, O, L> where L::Cursor: Flatten<'a> {
+	type Suffix = (&'a <L::Cursor as Cursor<'a>>::Key, <L::Cursor as Flatten<'a>>::Suffix);
+	type Iter = Tuples<'a, L::Cursor>;
+	type Group = Tuples<'a, CursorMerger<'a, L::Cursor>>;
+	fn flatten(val: L::Cursor) -> Self::Iter { Tuples::new(val) }
+	fn group(vals: Vec<L::Cursor>) -> Self::Group { Tuples::new(CursorMerger::from(vals.into_iter())) }
+}
+
+impl<'a, C: Flatten<'a>> Flatten<'a> for CursorMerger<'a, C> {
+	type Suffix = C::Suffix;
+	type Iter = C::Group;
+	type Group = C::Group;
+	fn flatten(vals: Vec<C::Val>) -> Self::Iter { C::group(vals) }
+	fn group(vals: Vec<Vec<C::Val>>) -> Self::Group {
+		C::group(vals.into_iter().flat_map(|x| x.into_iter()).collect())
+	}
+}
+
+impl<'a, K: Ord+'a, L: TrieRef<'a>> IntoIterator for &'a TrieLayer<K, L> where L::Cursor: Flatten<'a> {
+	type Item = (&'a K, <TrieCursor<'a, K, L> as Flatten<'a>>::Suffix);
+	type IntoIter = Tuples<'a, TrieCursor<'a, K, L>>;
+	fn into_iter(self) -> Self::IntoIter {
+		Tuples::new(self.enumerate())
+	}
+}
+
+/// Collects tuples in any order into a trie, as `TrieStorage::from_unordered`.
+impl<K: Ord+Clone, L: TrieStorage> FromIterator<(K, L::Item)> for TrieLayer<K, L> {
+	fn from_iter<I: IntoIterator<Item=(K, L::Item)>>(iter: I) -> Self {
+		TrieLayer::from_unordered(iter.into_iter())
+	}
+}
+
+/// Adds tuples in any order to a trie, by merging the trie with a trie of the tuples.
+///
+/// Each call copies the whole trie into the merged result, so extending a large trie
+/// many times with a few tuples each takes time quadratic in its size. Tuples should be
+/// gathered and added at once, or appended to an `Arbor`, which merges geometrically.
+impl<K: Ord+Clone, L: TrieStorage> Extend<(K, L::Item)> for TrieLayer<K, L> {
+	fn extend<I: IntoIterator<Item=(K, L::Item)>>(&mut self, iter: I) {
+		let other = TrieLayer::from_unordered(iter.into_iter());
+		if self.keys.is_empty() {
+			*self = other;
+		}
+		else if !other.keys.is_empty() {
+			*self = self.merge(&other);
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+
+	use std::collections::BTreeMap;
+
+	use {TrieStorage, TrieRef, CursorMerger};
+	use trie::TrieLayer;
+	use column::ColumnLayer;
+	use testing::triples;
+	use super::Tuples;
+
+	type Trie = TrieLayer<u32, TrieLayer<u32, Vec<(u32, i64)>>>;
+	type Tuple = (u32, (u32, (u32, i64)));
+
+	// the non-zero accumulations of `tuples`, in order.
+	fn consolidate(tuples: &[Tuple]) -> Vec<Tuple> {
+		let mut model = BTreeMap::new();
+		for &(key, (val, (time, diff))) in tuples {
+			*model.entry((key, val, time)).or_insert(0) += diff;
+		}
+		model.into_iter().filter(|x| x.1 != 0).map(|((key, val, time), diff)| (key, (val, (time, diff)))).collect()
+	}
+
+	fn owned(tuple: (&u32, (&u32, (&u32, &i64)))) -> Tuple {
+		(*tuple.0, (*(tuple.1).0, (*((tuple.1).1).0, *((tuple.1).1).1)))
+	}
+
+	#[test]
+	fn round_trip() {
+		let mut seed = 10;
+		for round in 0 .. 10 {
+			let tuples = triples(&mut seed, 1 + 50 * round, 20, 3);
+			let trie: Trie = tuples.iter().cloned().collect();
+			let found: Vec<Tuple> = trie.into_iter().map(owned).collect();
+			assert_eq!(found, consolidate(&tuples[..]));
+
+			// a consolidated trie rebuilt from its own tuples is unchanged.
+			let again: Trie = found.iter().cloned().collect();
+			assert_eq!(again.into_iter().map(owned).collect::<Vec<_>>(), found);
+
+			let columns = ColumnLayer::<u32, ColumnLayer<u32, Vec<(u32, i64)>>>::from_unordered(tuples.iter().cloned());
+			assert_eq!(Tuples::new(columns.enumerate()).map(owned).collect::<Vec<_>>(), found);
+		}
+	}
+
+	#[test]
+	fn extend_in_chunks() {
+		let mut seed = 11;
+		let tuples = triples(&mut seed, 500, 20, 3);
+		let mut trie = Trie::new();
+		for chunk in tuples.chunks(37) {
+			trie.extend(chunk.iter().cloned());
+		}
+		trie.extend(None);
+		assert!(trie.validate().is_ok());
+		assert_eq!(trie.into_iter().map(owned).collect::<Vec<_>>(), consolidate(&tuples[..]));
+	}
+
+	#[test]
+	fn merged_cursors() {
+		// tuples of several tries are presented once for each trie, in order.
+		let mut seed = 12;
+		let batches: Vec<Vec<Tuple>> = (0 .. 4).map(|_| triples(&mut seed, 100, 20, 3)).collect();
+		let tries: Vec<Trie> = batches.iter().map(|batch| batch.iter().cloned().collect()).collect();
+		let merger = CursorMerger::from(tries.iter().map(|trie| trie.enumerate()));
+		let found: Vec<Tuple> = Tuples::new(merger).map(owned).collect();
+
+		let mut expected: Vec<Tuple> = batches.iter().flat_map(|batch| consolidate(&batch[..])).collect();
+		expected.sort();
+		let mut sorted = found.clone();
+		sorted.sort();
+		assert_eq!(sorted, expected);
+		assert!(found.windows(2).all(|x| (x[0].0, (x[0].1).0, ((x[0].1).1).0) <= (x[1].0, (x[1].1).0, ((x[1].1).1).0)));
+		assert_eq!(consolidate(&found[..]), consolidate(&batches.concat()[..]));
+	}
+}
@@ -0,0 +1,460 @@
+//! A merge of many cursors using a binary heap.
+//!
+//! `CursorMerger` keeps its cursors in a sorted list, which is cheap for a few 
+//! cursors but costs time linear in their number whenever a cursor is exhausted
+//! or a large prefix must be re-sorted. `HeapMerger` keeps the cursors in a binary 
+//! heap instead, so that each advanced or exhausted cursor costs time logarithmic 
+//! in the number of cursors. It presents the same `next`, `seek` and `peek` methods,
+//! and is the better choice once there are more than a handful of cursors. Both may 
+//! also retreat from the end of their cursors, if these are `BackwardCursor`s.
+//!
+//! `AdaptiveMerger` makes that choice by the number of cursors it is given, using a 
+//! `CursorMerger` below `HEAP_THRESHOLD` cursors and a `HeapMerger` from then on.
+
+use trie::{Cursor, BackwardCursor};
+use merge::{CursorMerger, CursorView};
+
+/// A cursor-like merge of several cursors, ordered by a binary heap.
+pub struct HeapMerger<'a, C: Cursor<'a>> {
+	// the next item of each cursor, unless the cursor is exhausted.
+	heads: Vec<Option<(&'a C::Key, C::Val)>>,
+	cursors: Vec<C>,
+	// indices of cursors with a head, as a min-heap on their head keys.
+	heap: Vec<usize>,
+	// indices of cursors whose heads are presented by the current view.
+	active: Vec<usize>,
+}
+
+/// A view of merged results, reflecting a key and items with this key.
+///
+/// As with `CursorView`, the view holds a mut reference to the merger and must be 
+/// dropped before the merger may be advanced again.
+pub struct HeapView<'a, 'b, C> where 'a: 'b, C: Cursor<'a>+'b {
+	key: &'a C::Key,
+	pos: usize,
+	merger: &'b mut HeapMerger<'a, C>,
+}
+
+impl<'a, 'b, C> HeapView<'a, 'b, C> where 'a: 'b, C: Cursor<'a>+'b {
+	/// Returns the key being merged, unless all elements have been consumed.
+	pub fn key(&self) -> Option<&'a C::Key> {
+		if self.pos < self.merger.active.len() { Some(self.key) } else { None }
+	}
+	/// Returns the number of remaining elements in the merge.
+	pub fn len(&self) -> usize {
+		self.merger.active.len() - self.pos
+	}
+	/// Returns true if no elements remain in the merge.
+	pub fn is_empty(&self) -> bool {
+		self.pos == self.merger.active.len()
+	}
+}
+
+impl<'a, 'b, C> Iterator for HeapView<'a, 'b, C> where 'a: 'b, C: Cursor<'a>+'b {
+	type Item = C::Val;
+	fn next(&mut self) -> Option<Self::Item> {
+		if self.pos < self.merger.active.len() {
+			let index = self.merger.active[self.pos];
+			self.pos += 1;
+			self.merger.advance(index)
+		}
+		else {
+			None
+		}
+	}
+}
+
+impl<'a, 'b, C> Drop for HeapView<'a, 'b, C> where 'a: 'b, C: Cursor<'a>+'b {
+	fn drop(&mut self) {
+		while self.pos < self.merger.active.len() {
+			let index = self.merger.active[self.pos];
+			self.pos += 1;
+			self.merger.advance(index);
+		}
+		self.merger.active.clear();
+	}
+}
+
+impl<'a, C: Cursor<'a>> Default for HeapMerger<'a, C> {
+	fn default() -> Self { HeapMerger::new() }
+}
+
+impl<'a, C: Cursor<'a>> HeapMerger<'a, C> {
+	/// Creates a new, empty HeapMerger.
+	pub fn new() -> Self { 
+		HeapMerger::<'a, C> { 
+			heads: vec![], 
+			cursors: vec![], 
+			heap: vec![], 
+			active: vec![],
+		} 
+	}
+
+	/// Returns a view over the data of the next key, if any, and advances the cursor.
+	pub fn next<'b>(&'b mut self) -> Option<HeapView<'a, 'b, C>> {
+		if let Some(key) = self.peek() {
+			while self.peek() == Some(key) {
+				let index = self.pop();
+				self.active.push(index);
+			}
+			Some(HeapView { key: key, pos: 0, merger: self })
+		}
+		else {
+			None
+		}
+	}
+
+	/// Advances the HeapMerger to the first key at least as large as `key`.
+	#[inline(never)]
+	pub fn seek(&mut self, key: &C::Key) {
+		while self.peek().map(|x| x < key) == Some(true) {
+			let index = self.pop();
+			self.cursors[index].seek(key);
+			self.heads[index] = self.cursors[index].next();
+			if self.heads[index].is_some() {
+				self.insert(index);
+			}
+		}
+
+		debug_assert!(self.peek().map(|x| x >= key) != Some(false));
+	}
+
+	/// Reveals the next key, if one exists.
+	pub fn peek(&self) -> Option<&'a C::Key> {
+		self.heap.first().map(|&index| self.key(index))
+	}
+
+	/// Clears the HeapMerger.
+	pub fn clear(&mut self) {
+		self.heads.clear();
+		self.cursors.clear();
+		self.heap.clear();
+		self.active.clear();
+	}
+
+	/// Refills a HeapMerger from an iterator of Cursors, re-using allocated memory.
+	pub fn refill_from<I: Iterator<Item=C>>(&mut self, iterator: I) {
+		self.clear();
+		for cursor in iterator {
+			self.push(cursor);
+		}
+	}
+
+	/// Constructs a new HeapMerger from a iterator of Cursors.
+	pub fn from<I: Iterator<Item=C>>(iterator: I) -> Self {
+		let mut result = Self::new();
+		result.refill_from(iterator);
+		result
+	}
+
+	pub fn push(&mut self, mut cursor: C) {
+		if let Some(next) = cursor.next() {
+			self.heads.push(Some(next));
+			self.cursors.push(cursor);
+			let index = self.cursors.len() - 1;
+			self.insert(index);
+		}
+	}
+
+	// moves the cursor at `index` to its next item, returning the value of its current head.
+	fn advance(&mut self, index: usize) -> Option<C::Val> {
+		if let Some(next) = self.cursors[index].next() {
+			let head = self.heads[index].replace(next);
+			self.insert(index);
+			head.map(|x| x.1)
+		}
+		else {
+			self.heads[index].take().map(|x| x.1)
+		}
+	}
+
+	fn key(&self, index: usize) -> &'a C::Key {
+		self.heads[index].as_ref().unwrap().0
+	}
+
+	// adds `index` to the heap, restoring the heap order.
+	fn insert(&mut self, index: usize) {
+		let mut pos = self.heap.len();
+		self.heap.push(index);
+		while pos > 0 {
+			let parent = (pos - 1) / 2;
+			if self.key(self.heap[pos]) < self.key(self.heap[parent]) {
+				self.heap.swap(pos, parent);
+				pos = parent;
+			}
+			else {
+				break;
+			}
+		}
+	}
+
+	// removes and returns the index with the least key, restoring the heap order.
+	fn pop(&mut self) -> usize {
+		let result = self.heap.swap_remove(0);
+		let mut pos = 0;
+		loop {
+			let mut least = pos;
+			for child in 2 * pos + 1 .. ::std::cmp::min(2 * pos + 3, self.heap.len()) {
+				if self.key(self.heap[child]) < self.key(self.heap[least]) {
+					least = child;
+				}
+			}
+			if least == pos { break; }
+			self.heap.swap(pos, least);
+			pos = least;
+		}
+		result
+	}
+}
+
+impl<'a, C: BackwardCursor<'a>> HeapMerger<'a, C> {
+	/// Returns the last key and the values of each cursor with that key, retreating the merger.
+	///
+	/// The heap orders cursors by their first keys only, so this examines each merged cursor
+	/// and takes time linear in their number, as does `CursorMerger::prev`.
+	pub fn prev(&mut self) -> Option<(&'a C::Key, Vec<C::Val>)> {
+		if let Some(key) = self.peek_back() {
+			let mut vals = Vec::new();
+			let mut emptied = false;
+			for index in 0 .. self.cursors.len() {
+				if self.heads[index].is_some() {
+					if self.cursors[index].peek_back() == Some(key) {
+						vals.push(self.cursors[index].prev().unwrap().1);
+					}
+					else if self.cursors[index].peek_back().is_none() && self.key(index) == key {
+						vals.push(self.heads[index].take().unwrap().1);
+						emptied = true;
+					}
+				}
+			}
+			if emptied {
+				self.rebuild();
+			}
+			Some((key, vals))
+		}
+		else {
+			None
+		}
+	}
+
+	/// Retreats the HeapMerger to the last key no larger than `key`.
+	pub fn seek_back(&mut self, key: &C::Key) {
+		for index in 0 .. self.cursors.len() {
+			if self.heads[index].is_some() {
+				self.cursors[index].seek_back(key);
+				if self.key(index) > key {
+					self.heads[index] = None;
+				}
+			}
+		}
+		self.rebuild();
+	}
+
+	/// Reveals the last key, if one exists.
+	pub fn peek_back(&self) -> Option<&'a C::Key> {
+		(0 .. self.cursors.len())
+			.filter(|&index| self.heads[index].is_some())
+			.map(|index| self.cursors[index].peek_back().unwrap_or(self.key(index)))
+			.max()
+	}
+
+	// rebuilds the heap from the cursors that still have heads.
+	fn rebuild(&mut self) {
+		self.heap.clear();
+		for index in 0 .. self.cursors.len() {
+			if self.heads[index].is_some() {
+				self.insert(index);
+			}
+		}
+	}
+}
+
+/// The number of cursors from which an `AdaptiveMerger` merges with a `HeapMerger`.
+pub const HEAP_THRESHOLD: usize = 8;
+
+/// A cursor-like merge of several cursors, choosing its implementation by their number.
+pub enum AdaptiveMerger<'a, C: Cursor<'a>> {
+	/// A sorted list of cursors, for fewer than `HEAP_THRESHOLD` cursors.
+	Linear(CursorMerger<'a, C>),
+	/// A binary heap of cursors, for at least `HEAP_THRESHOLD` cursors.
+	Heap(HeapMerger<'a, C>),
+}
+
+/// A view of merged results from an `AdaptiveMerger`, reflecting a key and items with this key.
+pub enum AdaptiveView<'a, 'b, C> where 'a: 'b, C: Cursor<'a>+'b {
+	Linear(CursorView<'a, 'b, C>),
+	Heap(HeapView<'a, 'b, C>),
+}
+
+impl<'a, 'b, C> AdaptiveView<'a, 'b, C> where 'a: 'b, C: Cursor<'a>+'b {
+	/// Returns the key being merged, unless all elements have been consumed.
+	pub fn key(&self) -> Option<&'a C::Key> {
+		match *self {
+			AdaptiveView::Linear(ref view) => view.key(),
+			AdaptiveView::Heap(ref view) => view.key(),
+		}
+	}
+	/// Returns the number of remaining elements in the merge.
+	pub fn len(&self) -> usize {
+		match *self {
+			AdaptiveView::Linear(ref view) => view.len(),
+			AdaptiveView::Heap(ref view) => view.len(),
+		}
+	}
+	/// Returns true if no elements remain in the merge.
+	pub fn is_empty(&self) -> bool {
+		self.len() == 0
+	}
+}
+
+impl<'a, 'b, C> Iterator for AdaptiveView<'a, 'b, C> where 'a: 'b, C: Cursor<'a>+'b {
+	type Item = C::Val;
+	fn next(&mut self) -> Option<Self::Item> {
+		match *self {
+			AdaptiveView::Linear(ref mut view) => view.next(),
+			AdaptiveView::Heap(ref mut view) => view.next(),
+		}
+	}
+}
+
+impl<'a, C: Cursor<'a>> AdaptiveMerger<'a, C> {
+	/// Constructs a merger from an iterator of cursors, using a heap if there are at 
+	/// least `HEAP_THRESHOLD` of them.
+	pub fn from<I: Iterator<Item=C>>(iterator: I) -> Self {
+		let cursors: Vec<C> = iterator.collect();
+		if cursors.len() < HEAP_THRESHOLD {
+			AdaptiveMerger::Linear(CursorMerger::from(cursors.into_iter()))
+		}
+		else {
+			AdaptiveMerger::Heap(HeapMerger::from(cursors.into_iter()))
+		}
+	}
+
+	/// Returns a view over the data of the next key, if any, and advances the cursor.
+	pub fn next<'b>(&'b mut self) -> Option<AdaptiveView<'a, 'b, C>> {
+		match *self {
+			AdaptiveMerger::Linear(ref mut merger) => merger.next().map(AdaptiveView::Linear),
+			AdaptiveMerger::Heap(ref mut merger) => merger.next().map(AdaptiveView::Heap),
+		}
+	}
+
+	/// Advances the merger to the first key at least as large as `key`.
+	pub fn seek(&mut self, key: &C::Key) {
+		match *self {
+			AdaptiveMerger::Linear(ref mut merger) => merger.seek(key),
+			AdaptiveMerger::Heap(ref mut merger) => merger.seek(key),
+		}
+	}
+
+	/// Reveals the next key, if one exists.
+	pub fn peek(&self) -> Option<&'a C::Key> {
+		match *self {
+			AdaptiveMerger::Linear(ref merger) => merger.peek(),
+			AdaptiveMerger::Heap(ref merger) => merger.peek(),
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+
+	use trie::TrieRef;
+	use merge::CursorMerger;
+	use testing::random;
+	use super::{HeapMerger, AdaptiveMerger, HEAP_THRESHOLD};
+
+	// `count` sorted lists of overlapping keys, each key paired with its list's number.
+	fn lists(seed: &mut u64, count: usize) -> Vec<Vec<(u32, usize)>> {
+		(0 .. count).map(|list| {
+			let mut keys: Vec<u32> = (0 .. random(seed) % 60).map(|_| (random(seed) % 100) as u32).collect();
+			keys.sort();
+			keys.dedup();
+			keys.into_iter().map(|key| (key, list)).collect()
+		}).collect()
+	}
+
+	// drains a merger as keys and sorted values, seeking to each key of `seeks` once the 
+	// previous key passes it. each merger type has its own `next`, so this is a macro.
+	macro_rules! drain {
+		($merger:expr, $seeks:expr) => {{
+			let mut merger = $merger;
+			let mut seeks = $seeks.iter();
+			let mut result = Vec::new();
+			while let Some(key) = merger.peek() {
+				if let Some(&target) = seeks.next() {
+					if target > *key { merger.seek(&target); }
+				}
+				if let Some(view) = merger.next() {
+					let key = *view.key().unwrap();
+					let mut vals: Vec<usize> = view.map(|&x| x).collect();
+					vals.sort();
+					result.push((key, vals));
+				}
+			}
+			result
+		}}
+	}
+
+	#[test]
+	fn agrees_with_cursor_merger() {
+		let mut seed = 5;
+		for count in 0 .. 24 {
+			let lists = lists(&mut seed, count);
+			let seeks: Vec<u32> = (0 .. 10).map(|step| (step * step) as u32).collect();
+			for seeks in &[vec![], seeks] {
+				let linear = drain!(CursorMerger::from(lists.iter().map(|x| x.enumerate())), seeks);
+				let heap = drain!(HeapMerger::from(lists.iter().map(|x| x.enumerate())), seeks);
+				let adaptive = drain!(AdaptiveMerger::from(lists.iter().map(|x| x.enumerate())), seeks);
+				assert_eq!(heap, linear);
+				assert_eq!(adaptive, linear);
+			}
+			match AdaptiveMerger::from(lists.iter().map(|x| x.enumerate())) {
+				AdaptiveMerger::Linear(_) => assert!(count < HEAP_THRESHOLD),
+				AdaptiveMerger::Heap(_) => assert!(count >= HEAP_THRESHOLD),
+			}
+		}
+	}
+
+	#[test]
+	fn forward_then_backward() {
+		let no_seeks: &[u32] = &[];
+		let mut seed = 8;
+		for count in 0 .. 12 {
+			let lists = lists(&mut seed, count);
+			let forward = drain!(HeapMerger::from(lists.iter().map(|x| x.enumerate())), no_seeks);
+
+			// consume a prefix forwards and the remainder backwards, which together see every key.
+			for split in 0 .. forward.len() + 1 {
+				let mut merger = HeapMerger::from(lists.iter().map(|x| x.enumerate()));
+				let mut found = Vec::new();
+				for _ in 0 .. split {
+					let view = merger.next().unwrap();
+					let key = *view.key().unwrap();
+					let mut vals: Vec<usize> = view.map(|&x| x).collect();
+					vals.sort();
+					found.push((key, vals));
+				}
+				let mut backward = Vec::new();
+				while let Some((key, vals)) = merger.prev() {
+					let mut vals: Vec<usize> = vals.into_iter().map(|&x| x).collect();
+					vals.sort();
+					backward.push((*key, vals));
+				}
+				assert!(merger.peek().is_none());
+				backward.reverse();
+				found.extend(backward);
+				assert_eq!(found, forward);
+			}
+
+			// seeking backwards leaves the keys no larger than the target, which may still be 
+			// consumed forwards.
+			for &target in &[0, 10, 50, 99] {
+				let mut merger = HeapMerger::from(lists.iter().map(|x| x.enumerate()));
+				merger.seek_back(&target);
+				let expected: Vec<_> = forward.iter().filter(|x| x.0 <= target).cloned().collect();
+				assert_eq!(merger.peek_back(), expected.last().map(|x| &x.0));
+				assert_eq!(drain!(merger, no_seeks), expected);
+			}
+		}
+	}
+}
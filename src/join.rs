@@ -0,0 +1,141 @@
+//! Worst-case optimal intersection of cursors, in the style of leapfrog triejoin.
+//!
+//! A `Leapfrog` enumerates the keys present in each of several cursors, using 
+//! `seek` to skip past keys that cannot be in the intersection. Each cursor is 
+//! advanced to the largest key any other cursor has proposed, which bounds the 
+//! number of seeks by the size of the smallest cursor times the number of cursors.
+//!
+//! Values for a key found in all cursors are handed back to the caller, who may 
+//! then form a `Leapfrog` over the nested cursors to intersect the next attribute.
+//! Cursors over an `Arbor` are `CursorMerger`s, whose values are the list of nested
+//! cursors from each trie; these can be re-merged with `CursorMerger::from`.
+
+use trie::Cursor;
+
+/// An intersection of several cursors with the same key type.
+pub struct Leapfrog<C> {
+	cursors: Vec<C>,
+}
+
+impl<'a, C: Cursor<'a>> Leapfrog<C> {
+
+	/// Creates a new intersection of the supplied cursors.
+	pub fn new(cursors: Vec<C>) -> Leapfrog<C> {
+		Leapfrog { cursors: cursors }
+	}
+
+	/// Advances to the next key present in all cursors, and moves their values into `vals`.
+	///
+	/// The values are pushed in the order of the cursors supplied to `new`. If no further
+	/// key is common to all cursors, `vals` is left empty and the method returns `None`.
+	pub fn next_into(&mut self, vals: &mut Vec<C::Val>) -> Option<&'a C::Key> {
+
+		vals.clear();
+		if self.cursors.is_empty() { return None; }
+
+		let mut max = self.cursors[0].peek()?;
+
+		// leapfrog through the cursors until each agrees on `max`.
+		let mut agreed = 1;
+		let mut index = 1 % self.cursors.len();
+		while agreed < self.cursors.len() {
+			self.cursors[index].seek(max);
+			match self.cursors[index].peek() {
+				Some(key) => {
+					if key == max { agreed += 1; } 
+					else { 
+						max = key; 
+						agreed = 1; 
+					}
+				}
+				None => { return None; }
+			}
+			index = (index + 1) % self.cursors.len();
+		}
+
+		for cursor in &mut self.cursors {
+			vals.push(cursor.next().unwrap().1);
+		}
+
+		Some(max)
+	}
+
+	/// Advances each cursor to the first element with key greater or equal to `key`.
+	pub fn seek(&mut self, key: &C::Key) {
+		for cursor in &mut self.cursors {
+			cursor.seek(key);
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+
+	use trie::TrieRef;
+	use testing::random;
+	use super::Leapfrog;
+
+	// distinct sorted keys less than `keys`, each paired with a value naming its relation.
+	fn relation(seed: &mut u64, count: usize, keys: u64, name: u32) -> Vec<(u32, u32)> {
+		let mut found: Vec<u32> = (0 .. count).map(|_| (random(seed) % keys) as u32).collect();
+		found.sort();
+		found.dedup();
+		found.into_iter().map(|key| (key, 1000 * name + key)).collect()
+	}
+
+	// the keys of all `relations` at least `lower`, with their values, by nested loops.
+	fn nested(relations: &[Vec<(u32, u32)>], lower: u32) -> Vec<(u32, Vec<u32>)> {
+		let mut result = Vec::new();
+		for &(key, _) in relations[0].iter().filter(|x| x.0 >= lower) {
+			let vals: Vec<u32> = relations.iter().filter_map(|relation| relation.iter().find(|x| x.0 == key).map(|x| x.1)).collect();
+			if vals.len() == relations.len() {
+				result.push((key, vals));
+			}
+		}
+		result
+	}
+
+	// the keys of all `relations` at least `lower`, with their values, by a leapfrog join.
+	fn leapfrog(relations: &[Vec<(u32, u32)>], lower: u32) -> Vec<(u32, Vec<u32>)> {
+		let mut join = Leapfrog::new(relations.iter().map(|relation| relation.enumerate()).collect());
+		join.seek(&lower);
+		let mut vals = Vec::new();
+		let mut result = Vec::new();
+		while let Some(key) = join.next_into(&mut vals) {
+			result.push((*key, vals.iter().map(|&&val| val).collect()));
+		}
+		assert!(vals.is_empty());
+		result
+	}
+
+	#[test]
+	fn agrees_with_nested_loops() {
+		let mut seed = 7;
+		for round in 0 .. 50 {
+			for &count in &[2, 3] {
+				let relations: Vec<_> = (0 .. count).map(|name| relation(&mut seed, 5 + round * 3, 100, name)).collect();
+				for &lower in &[0, 17, 50, 99, 100] {
+					assert_eq!(leapfrog(&relations[..], lower), nested(&relations[..], lower));
+				}
+			}
+		}
+	}
+
+	#[test]
+	fn empty_and_disjoint_inputs() {
+		let evens: Vec<(u32, u32)> = (0 .. 50).map(|x| (2 * x, 0)).collect();
+		let odds: Vec<(u32, u32)> = (0 .. 50).map(|x| (2 * x + 1, 1)).collect();
+		let threes: Vec<(u32, u32)> = (0 .. 34).map(|x| (3 * x, 2)).collect();
+		let empty: Vec<(u32, u32)> = Vec::new();
+
+		assert!(leapfrog(&[evens.clone(), odds.clone()], 0).is_empty());
+		assert!(leapfrog(&[evens.clone(), threes.clone(), odds], 0).is_empty());
+		assert!(leapfrog(&[evens.clone(), empty.clone()], 0).is_empty());
+		assert!(leapfrog(&[empty.clone(), evens.clone(), threes.clone()], 0).is_empty());
+		assert!(leapfrog(&[empty.clone(), empty], 0).is_empty());
+
+		let sixes = leapfrog(&[evens.clone(), threes.clone()], 0);
+		assert_eq!(sixes, nested(&[evens, threes], 0));
+		assert_eq!(sixes.len(), 17);
+	}
+}
@@ -0,0 +1,101 @@
+//! Partially ordered times, and their advancement by frontiers.
+//!
+//! Times in a trie are stored in the order given by `Ord`, which must be a linear
+//! extension of the partial order given by `less_equal`. The partial order is what
+//! determines whether one time is in advance of another.
+
+/// A partially ordered type with least upper and greatest lower bounds.
+pub trait Lattice : Ord+Clone {
+	/// Returns true if `self` is less or equal to `other` in the partial order.
+	fn less_equal(&self, other: &Self) -> bool;
+	/// The least upper bound of `self` and `other`.
+	fn join(&self, other: &Self) -> Self;
+	/// The greatest lower bound of `self` and `other`.
+	fn meet(&self, other: &Self) -> Self;
+
+	/// Advances `self` to its representative with respect to `frontier`.
+	///
+	/// The representative is the meet of the joins of `self` with each element of the
+	/// frontier. Two times with the same representative compare identically to every 
+	/// time in advance of the frontier, and so their differences may be combined. An 
+	/// empty frontier leaves the time unchanged.
+	fn advance_by(&self, frontier: &[Self]) -> Self {
+		if let Some(first) = frontier.first() {
+			let mut result = self.join(first);
+			for time in &frontier[1..] {
+				result = result.meet(&self.join(time));
+			}
+			result
+		}
+		else {
+			self.clone()
+		}
+	}
+}
+
+macro_rules! implement_total {
+	($($t:ty),*) => {
+		$(
+			impl Lattice for $t {
+				#[inline(always)] fn less_equal(&self, other: &Self) -> bool { self <= other }
+				#[inline(always)] fn join(&self, other: &Self) -> Self { ::std::cmp::max(*self, *other) }
+				#[inline(always)] fn meet(&self, other: &Self) -> Self { ::std::cmp::min(*self, *other) }
+			}
+		)*
+	}
+}
+
+implement_total!((), u8, u16, u32, u64, usize, i8, i16, i32, i64, isize);
+
+/// Pairs of times are ordered by the product partial order.
+impl<A: Lattice, B: Lattice> Lattice for (A, B) {
+	fn less_equal(&self, other: &Self) -> bool {
+		self.0.less_equal(&other.0) && self.1.less_equal(&other.1)
+	}
+	fn join(&self, other: &Self) -> Self {
+		(self.0.join(&other.0), self.1.join(&other.1))
+	}
+	fn meet(&self, other: &Self) -> Self {
+		(self.0.meet(&other.0), self.1.meet(&other.1))
+	}
+}
+
+#[cfg(test)]
+mod tests {
+
+	use testing::random;
+	use super::Lattice;
+
+	fn time(seed: &mut u64) -> (u32, u32) {
+		((random(seed) % 6) as u32, (random(seed) % 6) as u32)
+	}
+
+	#[test]
+	fn advance_by_meets_joins() {
+		let frontier = [(2, 1), (1, 2)];
+		assert_eq!((0u32, 0u32).advance_by(&frontier), (1, 1));
+		assert_eq!((1u32, 3u32).advance_by(&frontier), (1, 3));
+		assert_eq!((3u32, 0u32).advance_by(&frontier), (3, 1));
+		assert_eq!((4u32, 4u32).advance_by(&[]), (4, 4));
+		assert_eq!(1u32.advance_by(&[2, 7]), 2);
+		assert_eq!(5u32.advance_by(&[2, 7]), 5);
+	}
+
+	#[test]
+	fn advance_by_preserves_comparisons() {
+		// an advanced time compares to every time in advance of the frontier as it did before.
+		let mut seed = 3;
+		for _ in 0 .. 500 {
+			let frontier = [time(&mut seed), time(&mut seed)];
+			let before = time(&mut seed);
+			let after = before.advance_by(&frontier);
+			assert!(before.less_equal(&after));
+			for _ in 0 .. 20 {
+				let other = time(&mut seed);
+				if frontier.iter().any(|time| time.less_equal(&other)) {
+					assert_eq!(before.less_equal(&other), after.less_equal(&other));
+				}
+			}
+		}
+	}
+}
@@ -14,11 +14,29 @@
 extern crate fnv;
 
 pub mod merge;
+pub mod join;
+pub mod heap;
+pub mod consolidate;
+pub mod flatten;
 pub mod arbor;
 pub mod arbor_index;
+pub mod policy;
+pub mod bloom;
 pub mod trie;
+pub mod column;
+pub mod set;
+pub mod lattice;
+pub mod time;
+pub mod persist;
+pub mod radix;
+
+#[cfg(test)]
+mod testing;
 
 pub use arbor::Arbor;
 pub use merge::CursorMerger;
+pub use join::Leapfrog;
+pub use heap::HeapMerger;
+pub use lattice::Lattice;
 
-pub use trie::{TrieStorage, TrieRef};
\ No newline at end of file
+pub use trie::{TrieStorage, TrieRef, Monoid};
\ No newline at end of file
@@ -1,4 +1,4 @@
-use trie::Cursor;
+use trie::{Cursor, BackwardCursor};
 
 /// A cursor-like merge of several cursors.
 pub struct CursorMerger<'a, C: Cursor<'a>> {
@@ -112,7 +112,7 @@ impl<'a, C: Cursor<'a>> CursorMerger<'a, C> {
 	}
 
 	/// Reveals the next key, if one exists.
-	pub fn peek(&mut self) -> Option<&'a C::Key> {
+	pub fn peek(&self) -> Option<&'a C::Key> {
 		if self.cursors.len() > 0 {
 			Some((&self.cursors[0].0).0)
 		}
@@ -180,3 +180,150 @@ impl<'a, C: Cursor<'a>> CursorMerger<'a, C> {
 	}
 }
 
+
+
+impl<'a, C: BackwardCursor<'a>> CursorMerger<'a, C> {
+	/// Returns the last key and the values of each cursor with that key, retreating the cursor.
+	///
+	/// Unlike `next`, this examines each merged cursor and takes time linear in their number.
+	pub fn prev(&mut self) -> Option<(&'a C::Key, Vec<C::Val>)> {
+		if let Some(key) = self.peek_back() {
+			let mut vals = Vec::new();
+			let mut index = 0;
+			while index < self.cursors.len() {
+				if self.cursors[index].1.peek_back() == Some(key) {
+					vals.push(self.cursors[index].1.prev().unwrap().1);
+					index += 1;
+				}
+				else if self.cursors[index].1.peek_back().is_none() && (self.cursors[index].0).0 == key {
+					vals.push((self.cursors.remove(index).0).1);
+				}
+				else {
+					index += 1;
+				}
+			}
+			Some((key, vals))
+		}
+		else {
+			None
+		}
+	}
+
+	/// Retreats the CursorMerger to the last key no larger than `key`.
+	pub fn seek_back(&mut self, key: &C::Key) {
+		for cursor in &mut self.cursors {
+			cursor.1.seek_back(key);
+		}
+		self.cursors.retain(|x| (x.0).0 <= key);
+	}
+
+	/// Reveals the last key, if one exists.
+	pub fn peek_back(&self) -> Option<&'a C::Key> {
+		self.cursors.iter().map(|x| x.1.peek_back().unwrap_or((x.0).0)).max()
+	}
+}
+
+/// Presents the merger as a cursor whose values are the gathered values of each key.
+///
+/// The reported `size` counts items across all merged cursors, and so is an upper 
+/// bound on the number of distinct keys remaining.
+impl<'a, C: Cursor<'a>> Cursor<'a> for CursorMerger<'a, C> {
+	type Key = C::Key;
+	type Val = Vec<C::Val>;
+
+	fn next(&mut self) -> Option<(&'a Self::Key, Self::Val)> {
+		CursorMerger::next(self).map(|view| (view.key().unwrap(), view.collect()))
+	}
+	fn seek(&mut self, key: &Self::Key) {
+		CursorMerger::seek(self, key)
+	}
+	fn peek(&self) -> Option<&'a Self::Key> {
+		CursorMerger::peek(self)
+	}
+	fn size(&self) -> usize {
+		self.cursors.iter().map(|x| 1 + x.1.size()).sum()
+	}
+}
+
+impl<'a, C: BackwardCursor<'a>> BackwardCursor<'a> for CursorMerger<'a, C> {
+	fn prev(&mut self) -> Option<(&'a Self::Key, Self::Val)> {
+		CursorMerger::prev(self)
+	}
+	fn seek_back(&mut self, key: &Self::Key) {
+		CursorMerger::seek_back(self, key)
+	}
+	fn peek_back(&self) -> Option<&'a Self::Key> {
+		CursorMerger::peek_back(self)
+	}
+}
+#[cfg(test)]
+mod tests {
+
+	use TrieStorage;
+	use trie::{Cursor, TrieRef, TrieLayer, SliceCursor};
+	use testing::pairs;
+	use super::CursorMerger;
+
+	type Trie = TrieLayer<u32, Vec<(u32, i64)>>;
+
+	// the sorted values and differences beneath the cursors of one key.
+	fn items(vals: Vec<SliceCursor<u32, i64>>) -> Vec<(u32, i64)> {
+		let mut items = Vec::new();
+		for mut cursor in vals {
+			while let Some((val, diff)) = cursor.next() {
+				items.push((*val, *diff));
+			}
+		}
+		items.sort();
+		items
+	}
+
+	#[test]
+	fn forward_then_backward() {
+		let mut seed = 6;
+		let tries: Vec<Trie> = (0 .. 5).map(|count| Trie::from_unordered(pairs(&mut seed, 10 * count, 60, 4).into_iter())).collect();
+		let merger = || CursorMerger::from(tries.iter().map(|trie| trie.enumerate()));
+
+		let mut forward = Vec::new();
+		let mut cursor = merger();
+		while let Some(view) = cursor.next() {
+			let key = *view.key().unwrap();
+			forward.push((key, items(view.collect())));
+		}
+
+		// consume a prefix forwards and the remainder backwards, which together see every key.
+		for split in 0 .. forward.len() + 1 {
+			let mut cursor = merger();
+			let mut found = Vec::new();
+			for _ in 0 .. split {
+				let view = cursor.next().unwrap();
+				let key = *view.key().unwrap();
+				found.push((key, items(view.collect())));
+			}
+			let mut backward = Vec::new();
+			while let Some((key, vals)) = cursor.prev() {
+				if let Some(last) = cursor.peek_back() {
+					assert!(last < key);
+				}
+				backward.push((*key, items(vals)));
+			}
+			assert!(cursor.peek().is_none());
+			backward.reverse();
+			found.extend(backward);
+			assert_eq!(found, forward);
+		}
+
+		// seeking backwards leaves the keys no larger than the target.
+		for target in 0 .. 62 {
+			let mut cursor = merger();
+			cursor.seek_back(&target);
+			let mut backward = Vec::new();
+			while let Some((key, vals)) = cursor.prev() {
+				backward.push((*key, items(vals)));
+			}
+			backward.reverse();
+			let expected: Vec<_> = forward.iter().filter(|x| x.0 <= target).cloned().collect();
+			assert_eq!(backward, expected);
+		}
+	}
+}
@@ -0,0 +1,574 @@
+//! A binary format for tries and arbors, and read-only views of serialized tries.
+//!
+//! A serialized file starts with a header (the bytes `TRIE` and a format version),
+//! followed by the number of tries it contains and then each trie in turn. A trie
+//! is written one layer at a time, from the top down: each layer records its number
+//! of keys, and then its columns. Each column starts at a multiple of eight bytes,
+//! and a layer's offsets are written as `u64` values. An arbor with Bloom filters
+//! follows its tries with the density of its filters, which views ignore.
+//!
+//! Columns are written in the machine's native byte order, which allows a `View` to
+//! present them directly from a byte buffer, without deserializing each element. The
+//! buffer must be aligned to eight bytes, which is true of memory-mapped files and of
+//! the contents of a `Buffer`; reading owned tries copies each column out, and so
+//! accepts any buffer. Files written on machines with a different byte order are 
+//! rejected by their version number.
+//!
+//! Values of `isize` and `usize` are written as `i64` and `u64` whatever the width of
+//! the machine, and reading a value too large for the reading machine is an error.
+//! Their columns are not `Plain`, and so tries of them can be read but not viewed.
+//!
+//! Owned tries are checked with `TrieStorage::validate` once read. Views check only
+//! that each layer's offsets stay within the layer beneath, so that navigating them
+//! cannot slice out of bounds, and present keys in whatever order they were written.
+
+use std::io::{self, Read, Write};
+use std::iter::Once;
+
+use {TrieStorage, TrieRef, CursorMerger, Lattice};
+use trie::{Cursor, BackwardCursor, TrieLayer, advance, retreat};
+use time::TimeLayer;
+use column::{ColumnLayer, ColumnCursor, Offset, Offsets, OffsetSlice};
+use flatten::Flatten;
+
+const MAGIC: &[u8; 4] = b"TRIE";
+const VERSION: u32 = 1;
+
+/// Types that may be written and viewed as their in-memory bytes.
+///
+/// # Safety
+///
+/// Implementors must have no padding and no pointers, and every bit pattern must
+/// be a valid value.
+pub unsafe trait Plain : Copy+'static { }
+
+unsafe impl Plain for u8 { }
+unsafe impl Plain for u16 { }
+unsafe impl Plain for u32 { }
+unsafe impl Plain for u64 { }
+unsafe impl Plain for i8 { }
+unsafe impl Plain for i16 { }
+unsafe impl Plain for i32 { }
+unsafe impl Plain for i64 { }
+
+/// Types that are written as values of a fixed-width `Plain` type.
+pub trait Encode : Copy+'static {
+	/// The type as which values are written.
+	type Plain: Plain;
+	/// Converts a value to the type as which it is written.
+	fn encode(self) -> Self::Plain;
+	/// Converts a written value back, or returns `None` if it is out of range.
+	fn decode(plain: Self::Plain) -> Option<Self>;
+}
+
+impl<T: Plain> Encode for T {
+	type Plain = T;
+	fn encode(self) -> T { self }
+	fn decode(plain: T) -> Option<T> { Some(plain) }
+}
+
+macro_rules! implement_encode {
+	($($t:ty => $p:ty),*) => {
+		$(
+			impl Encode for $t {
+				type Plain = $p;
+				fn encode(self) -> $p { self as $p }
+				fn decode(plain: $p) -> Option<$t> {
+					if plain as $t as $p == plain { Some(plain as $t) } else { None }
+				}
+			}
+		)*
+	}
+}
+
+implement_encode!(isize => i64, usize => u64);
+
+/// Tries that may be written to and read from the binary format.
+pub trait Persist : TrieStorage {
+	/// Writes the columns of the trie.
+	fn write_columns<W: Write>(&self, writer: &mut W) -> io::Result<()>;
+	/// Reads an owned trie from its columns, advancing `bytes` past them.
+	///
+	/// The trie's invariants are not checked, as they are by `read_trie`.
+	fn read_columns(bytes: &mut &[u8]) -> io::Result<Self>;
+}
+
+/// Tries with a read-only view over their serialized columns.
+///
+/// Columns are presented in place, and so the keys and leaf values of a viewable trie
+/// must be `Plain`. Tries with `isize` or `usize` keys or values must instead be read.
+pub trait View<'a> : Persist {
+	/// The borrowed view of a serialized trie.
+	type View: TrieRef<'a>;
+	/// Presents a trie from its columns, advancing `bytes` past them.
+	fn view_columns(bytes: &mut &'a [u8]) -> io::Result<Self::View>;
+}
+
+/// Writes a trie, with a header, to `writer`.
+pub fn write<T: Persist, W: Write>(trie: &T, mut writer: W) -> io::Result<()> {
+	write_header(&mut writer, 1)?;
+	trie.write_columns(&mut writer)
+}
+
+/// Reads an owned trie written by `write`.
+pub fn read<T: Persist>(mut bytes: &[u8]) -> io::Result<T> {
+	if read_header(&mut bytes)? != 1 {
+		return Err(invalid("expected a single trie"));
+	}
+	read_trie(&mut bytes)
+}
+
+/// Reads an owned trie from its columns, advancing `bytes` past them.
+///
+/// Unlike `Persist::read_columns`, the trie is checked with `TrieStorage::validate`, and
+/// an error is returned if it violates any invariant.
+pub fn read_trie<T: Persist>(bytes: &mut &[u8]) -> io::Result<T> {
+	let trie = T::read_columns(bytes)?;
+	trie.validate().map_err(|violation| io::Error::new(io::ErrorKind::InvalidData, violation))?;
+	Ok(trie)
+}
+
+/// Presents a trie written by `write` directly from `bytes`.
+pub fn view<'a, T: View<'a>>(mut bytes: &'a [u8]) -> io::Result<T::View> {
+	if read_header(&mut bytes)? != 1 {
+		return Err(invalid("expected a single trie"));
+	}
+	T::view_columns(&mut bytes)
+}
+
+/// Writes the header of a file containing `tries` tries.
+pub fn write_header<W: Write>(writer: &mut W, tries: usize) -> io::Result<()> {
+	writer.write_all(MAGIC)?;
+	writer.write_all(&VERSION.to_ne_bytes())?;
+	write_column(writer, &[tries as u64])
+}
+
+/// Reads a file header, and returns the number of tries in the file.
+pub fn read_header(bytes: &mut &[u8]) -> io::Result<usize> {
+	if bytes.len() < 8 || &bytes[.. 4] != MAGIC {
+		return Err(invalid("missing header"));
+	}
+	let mut version = [0u8; 4];
+	version.copy_from_slice(&bytes[4 .. 8]);
+	if u32::from_ne_bytes(version) != VERSION {
+		return Err(invalid("unsupported version or byte order"));
+	}
+	*bytes = &bytes[8 ..];
+	read_len(bytes)
+}
+
+/// Writes a column of plain data, padded to a multiple of eight bytes.
+pub fn write_column<T: Plain, W: Write>(writer: &mut W, column: &[T]) -> io::Result<()> {
+	let len = ::std::mem::size_of_val(column);
+	let bytes = unsafe { ::std::slice::from_raw_parts(column.as_ptr() as *const u8, len) };
+	writer.write_all(bytes)?;
+	writer.write_all(&[0u8; 8][.. padding(len)])
+}
+
+/// Reads a column of `len` elements of plain data, advancing `bytes` past it.
+///
+/// The column is presented in place, and an error is returned if `bytes` is too short
+/// or is not suitably aligned.
+pub fn read_column<'a, T: Plain>(bytes: &mut &'a [u8], len: usize) -> io::Result<&'a [T]> {
+	let size = len.checked_mul(::std::mem::size_of::<T>()).ok_or_else(|| invalid("column too long"))?;
+	if bytes.len() < size + padding(size) {
+		return Err(invalid("column extends past the end of the data"));
+	}
+	if bytes.as_ptr().align_offset(::std::mem::align_of::<T>()) != 0 {
+		return Err(invalid("column is not aligned"));
+	}
+	let column = unsafe { ::std::slice::from_raw_parts(bytes.as_ptr() as *const T, len) };
+	*bytes = &bytes[size + padding(size) ..];
+	Ok(column)
+}
+
+/// Writes a column of values, each as its fixed-width `Plain` type.
+pub fn write_encoded<T: Encode, W: Write>(writer: &mut W, column: &[T]) -> io::Result<()> {
+	let column: Vec<T::Plain> = column.iter().map(|&x| x.encode()).collect();
+	write_column(writer, &column[..])
+}
+
+/// Reads a column of `len` values written by `write_encoded`, advancing `bytes` past it.
+///
+/// The column is copied out, so `bytes` need not be aligned, and an error is returned
+/// if `bytes` is too short or holds a value out of range for `T`.
+pub fn read_encoded<T: Encode>(bytes: &mut &[u8], len: usize) -> io::Result<Vec<T>> {
+	let size = len.checked_mul(::std::mem::size_of::<T::Plain>()).ok_or_else(|| invalid("column too long"))?;
+	if bytes.len() < size + padding(size) {
+		return Err(invalid("column extends past the end of the data"));
+	}
+	let mut plain = Vec::<T::Plain>::with_capacity(len);
+	unsafe {
+		::std::ptr::copy_nonoverlapping(bytes.as_ptr(), plain.as_mut_ptr() as *mut u8, size);
+		plain.set_len(len);
+	}
+	*bytes = &bytes[size + padding(size) ..];
+	plain.into_iter().map(|x| T::decode(x).ok_or_else(|| invalid("value out of range"))).collect()
+}
+
+/// Reads the length that starts a layer, advancing `bytes` past it.
+///
+/// An error is returned if the length exceeds the number of remaining bytes.
+pub fn read_len(bytes: &mut &[u8]) -> io::Result<usize> {
+	if bytes.len() < 8 {
+		return Err(invalid("column extends past the end of the data"));
+	}
+	let mut len = [0u8; 8];
+	len.copy_from_slice(&bytes[.. 8]);
+	let len = u64::from_ne_bytes(len);
+	*bytes = &bytes[8 ..];
+	if len > (bytes.len() as u64) {
+		return Err(invalid("length exceeds the remaining data"));
+	}
+	Ok(len as usize)
+}
+
+fn padding(len: usize) -> usize { (8 - len % 8) % 8 }
+
+fn invalid(message: &str) -> io::Error {
+	io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+/// An eight-byte aligned buffer of bytes, suitable for viewing serialized tries.
+pub struct Buffer {
+	words: Vec<u64>,
+	len: usize,
+}
+
+impl Buffer {
+	/// Reads the remaining contents of `reader` into a new buffer.
+	pub fn read_from<R: Read>(mut reader: R) -> io::Result<Buffer> {
+		let mut bytes = Vec::new();
+		reader.read_to_end(&mut bytes)?;
+		let mut words = vec![0u64; (bytes.len() + 7) / 8];
+		unsafe { ::std::ptr::copy_nonoverlapping(bytes.as_ptr(), words.as_mut_ptr() as *mut u8, bytes.len()); }
+		Ok(Buffer { words: words, len: bytes.len() })
+	}
+	/// The contents of the buffer.
+	pub fn as_bytes(&self) -> &[u8] {
+		unsafe { ::std::slice::from_raw_parts(self.words.as_ptr() as *const u8, self.len) }
+	}
+}
+
+impl<K: Ord+Clone+Encode, L: Persist> Persist for TrieLayer<K, L> {
+	fn write_columns<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+		let keys: Vec<K::Plain> = self.keys.iter().map(|x| x.0.encode()).collect();
+		let offs: Vec<u64> = self.keys.iter().map(|x| x.1 as u64).collect();
+		write_column(writer, &[keys.len() as u64])?;
+		write_column(writer, &keys[..])?;
+		write_column(writer, &offs[..])?;
+		self.vals.write_columns(writer)
+	}
+	fn read_columns(bytes: &mut &[u8]) -> io::Result<Self> {
+		let len = read_len(bytes)?;
+		let keys = read_encoded::<K>(bytes, len)?;
+		let offs = read_encoded::<u64>(bytes, len)?;
+		let vals = L::read_columns(bytes)?;
+		check_offsets(&offs[..], vals.keys())?;
+		Ok(TrieLayer {
+			keys: keys.into_iter().zip(offs).map(|(k, o)| (k, o as usize)).collect(),
+			vals: vals,
+		})
+	}
+}
+
+impl<'a, K: Ord+Clone+Plain, L: View<'a>> View<'a> for TrieLayer<K, L> {
+	type View = LayerView<'a, K, L::View>;
+	fn view_columns(bytes: &mut &'a [u8]) -> io::Result<Self::View> {
+		let len = read_len(bytes)?;
+		let keys = read_column::<K>(bytes, len)?;
+		let offs = read_column::<u64>(bytes, len)?;
+		let vals = L::view_columns(bytes)?;
+		check_offsets(offs, vals.keys_cnt())?;
+		Ok(LayerView { keys: keys, offs: offs, vals: vals })
+	}
+}
+
+impl<K: Ord+Clone+Encode, L: Persist, O: Offset> Persist for ColumnLayer<K, L, O> {
+	fn write_columns<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+		let offs: Vec<u64> = (0 .. self.offs.len()).map(|i| self.offs.get(i) as u64).collect();
+		write_column(writer, &[self.keys.len() as u64])?;
+		write_encoded(writer, &self.keys[..])?;
+		write_column(writer, &offs[..])?;
+		self.vals.write_columns(writer)
+	}
+	fn read_columns(bytes: &mut &[u8]) -> io::Result<Self> {
+		let len = read_len(bytes)?;
+		let keys = read_encoded::<K>(bytes, len)?;
+		let offs = read_encoded::<u64>(bytes, len)?;
+		let vals = L::read_columns(bytes)?;
+		check_offsets(&offs[..], vals.keys())?;
+		let mut columns = Offsets::with_capacity(offs.len());
+		for &offset in offs.iter() {
+			columns.push(offset as usize);
+		}
+		Ok(ColumnLayer {
+			keys: keys,
+			offs: columns,
+			vals: vals,
+		})
+	}
+}
+
+impl<'a, K: Ord+Clone+Plain, L: View<'a>, O: Offset> View<'a> for ColumnLayer<K, L, O> {
+	type View = LayerView<'a, K, L::View>;
+	fn view_columns(bytes: &mut &'a [u8]) -> io::Result<Self::View> {
+		TrieLayer::<K, L>::view_columns(bytes)
+	}
+}
+
+impl<T: Lattice+Encode, L: Persist> Persist for TimeLayer<T, L> {
+	fn write_columns<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+		self.layer.write_columns(writer)
+	}
+	fn read_columns(bytes: &mut &[u8]) -> io::Result<Self> {
+		Ok(TimeLayer { layer: TrieLayer::read_columns(bytes)? })
+	}
+}
+
+impl<'a, T: Lattice+Plain, L: View<'a>> View<'a> for TimeLayer<T, L> {
+	type View = LayerView<'a, T, L::View>;
+	fn view_columns(bytes: &mut &'a [u8]) -> io::Result<Self::View> {
+		TrieLayer::<T, L>::view_columns(bytes)
+	}
+}
+
+impl<K: Ord+Clone+Encode, R: ::Monoid+Encode> Persist for Vec<(K, R)> {
+	fn write_columns<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+		let keys: Vec<K::Plain> = self.iter().map(|x| x.0.encode()).collect();
+		let vals: Vec<R::Plain> = self.iter().map(|x| x.1.encode()).collect();
+		write_column(writer, &[keys.len() as u64])?;
+		write_column(writer, &keys[..])?;
+		write_column(writer, &vals[..])
+	}
+	fn read_columns(bytes: &mut &[u8]) -> io::Result<Self> {
+		let len = read_len(bytes)?;
+		let keys = read_encoded::<K>(bytes, len)?;
+		let vals = read_encoded::<R>(bytes, len)?;
+		Ok(keys.into_iter().zip(vals).collect())
+	}
+}
+
+impl<'a, K: Ord+Clone+Plain, R: ::Monoid+Plain> View<'a> for Vec<(K, R)> {
+	type View = LeafView<'a, K, R>;
+	fn view_columns(bytes: &mut &'a [u8]) -> io::Result<Self::View> {
+		let len = read_len(bytes)?;
+		let keys = read_column::<K>(bytes, len)?;
+		let vals = read_column::<R>(bytes, len)?;
+		Ok(LeafView { keys: keys, vals: vals })
+	}
+}
+
+// checks that offsets do not decrease, and end at the number of keys in the layer beneath.
+//
+// keys are not checked, and views of malformed files may present them out of order or
+// with empty ranges of values, but navigating them will not slice out of bounds.
+fn check_offsets(offs: &[u64], keys: usize) -> io::Result<()> {
+	let mut lower = 0;
+	for &upper in offs {
+		if upper < lower || upper > keys as u64 {
+			return Err(invalid("offsets do not match the layer beneath"));
+		}
+		lower = upper;
+	}
+	if lower != keys as u64 {
+		return Err(invalid("offsets do not match the layer beneath"));
+	}
+	Ok(())
+}
+
+/// A read-only view of a serialized `TrieLayer`.
+pub struct LayerView<'a, K: 'a, L> {
+	keys: &'a [K],
+	offs: &'a [u64],
+	vals: L,
+}
+
+impl<'a, K: Ord+'a, L: TrieRef<'a>> TrieRef<'a> for LayerView<'a, K, L> {
+	type Cursor = ColumnCursor<'a, K, u64, L>;
+	fn keys_cnt(&self) -> usize { self.keys.len() }
+	fn cursor(&'a self, lower: usize, upper: usize) -> Self::Cursor {
+		let mut cursor = ColumnCursor::new(&self.keys[.. upper], OffsetSlice::new(&self.offs[.. upper], &[]), &self.vals);
+		cursor.index = lower;
+		cursor
+	}
+}
+
+/// A read-only view of a serialized `Vec<(K, R)>` leaf.
+pub struct LeafView<'a, K: 'a, R: 'a> {
+	keys: &'a [K],
+	vals: &'a [R],
+}
+
+impl<'a, K: Ord+'a, R: 'a> TrieRef<'a> for LeafView<'a, K, R> {
+	type Cursor = LeafCursor<'a, K, R>;
+	fn keys_cnt(&self) -> usize { self.keys.len() }
+	fn cursor(&'a self, lower: usize, upper: usize) -> Self::Cursor {
+		LeafCursor {
+			keys: &self.keys[lower .. upper],
+			vals: &self.vals[lower .. upper],
+		}
+	}
+}
+
+/// A cursor over the keys of a `LeafView`.
+pub struct LeafCursor<'a, K: 'a, R: 'a> {
+	keys: &'a [K],
+	vals: &'a [R],
+}
+
+impl<'a, K: Ord+'a, R: 'a> Cursor<'a> for LeafCursor<'a, K, R> {
+	type Key = K;
+	type Val = &'a R;
+	fn next(&mut self) -> Option<(&'a K, &'a R)> {
+		if !self.keys.is_empty() {
+			let result = (&self.keys[0], &self.vals[0]);
+			self.keys = &self.keys[1 ..];
+			self.vals = &self.vals[1 ..];
+			Some(result)
+		}
+		else {
+			None
+		}
+	}
+	fn seek(&mut self, key: &K) {
+		let count = advance(self.keys, |x| x < key);
+		self.keys = &self.keys[count ..];
+		self.vals = &self.vals[count ..];
+	}
+	fn peek(&self) -> Option<&'a K> {
+		self.keys.first()
+	}
+	fn size(&self) -> usize {
+		self.keys.len()
+	}
+}
+
+impl<'a, K: Ord+'a, R: 'a> BackwardCursor<'a> for LeafCursor<'a, K, R> {
+	fn prev(&mut self) -> Option<(&'a K, &'a R)> {
+		if !self.keys.is_empty() {
+			let last = self.keys.len() - 1;
+			let result = (&self.keys[last], &self.vals[last]);
+			self.keys = &self.keys[.. last];
+			self.vals = &self.vals[.. last];
+			Some(result)
+		}
+		else {
+			None
+		}
+	}
+	fn seek_back(&mut self, key: &K) {
+		let count = retreat(self.keys, |x| x > key);
+		self.keys = &self.keys[.. self.keys.len() - count];
+		self.vals = &self.vals[.. self.vals.len() - count];
+	}
+	fn peek_back(&self) -> Option<&'a K> {
+		self.keys.last()
+	}
+}
+
+impl<'a, K: Ord+'a, R: 'a> Flatten<'a> for LeafCursor<'a, K, R> {
+	type Suffix = &'a R;
+	type Iter = Once<&'a R>;
+	type Group = ::std::vec::IntoIter<&'a R>;
+	fn flatten(val: &'a R) -> Self::Iter { ::std::iter::once(val) }
+	fn group(vals: Vec<&'a R>) -> Self::Group { vals.into_iter() }
+}
+
+/// A read-only view of a serialized `Arbor`.
+pub struct ArborView<'a, T: View<'a>> {
+	tries: Vec<T::View>,
+}
+
+impl<'a, T: View<'a>> ArborView<'a, T> {
+	/// Presents the tries written by `Arbor::write_to` directly from `bytes`.
+	pub fn new(mut bytes: &'a [u8]) -> io::Result<ArborView<'a, T>> {
+		let count = read_header(&mut bytes)?;
+		let mut tries = Vec::with_capacity(count);
+		for _ in 0 .. count {
+			tries.push(T::view_columns(&mut bytes)?);
+		}
+		Ok(ArborView { tries: tries })
+	}
+
+	/// Provides a cursor for traversing the contents of all tries.
+	pub fn cursor(&'a self) -> CursorMerger<'a, <T::View as TrieRef<'a>>::Cursor> {
+		CursorMerger::from(self.tries.iter().map(|trie| trie.enumerate()))
+	}
+}
+
+#[cfg(test)]
+mod tests {
+
+	use super::{Encode, Buffer, write, read, view, read_encoded};
+	use TrieStorage;
+	use trie::TrieLayer;
+
+	type Trie = TrieLayer<u32, Vec<(u32, i64)>>;
+
+	// `trie` written and then read back, and viewed in place.
+	fn read_and_view(trie: &Trie) -> (bool, bool) {
+		let mut bytes = Vec::new();
+		write(trie, &mut bytes).unwrap();
+		let buffer = Buffer::read_from(&bytes[..]).unwrap();
+		(read::<Trie>(&bytes[..]).is_ok(), view::<Trie>(buffer.as_bytes()).is_ok())
+	}
+
+	fn leaves(count: u32) -> Vec<(u32, i64)> {
+		(0 .. count).map(|x| (x, 1)).collect()
+	}
+
+	#[test]
+	fn malformed_offsets() {
+		let valid = TrieLayer { keys: vec![(1, 2), (2, 3), (3, 5)], vals: leaves(5) };
+		assert_eq!(read_and_view(&valid), (true, true));
+		// offsets that decrease, or exceed the leaves, before ending correctly.
+		let decreasing = TrieLayer { keys: vec![(1, 3), (2, 2), (3, 5)], vals: leaves(5) };
+		assert_eq!(read_and_view(&decreasing), (false, false));
+		let exceeding = TrieLayer { keys: vec![(1, 2), (2, 7), (3, 5)], vals: leaves(5) };
+		assert_eq!(read_and_view(&exceeding), (false, false));
+		let short = TrieLayer { keys: vec![(1, 2), (2, 3), (3, 4)], vals: leaves(5) };
+		assert_eq!(read_and_view(&short), (false, false));
+	}
+
+	#[test]
+	fn owned_reads_validate() {
+		// well-formed offsets, but keys out of order, an empty range, and a zero difference.
+		let unordered = TrieLayer { keys: vec![(2, 2), (1, 5)], vals: leaves(5) };
+		assert_eq!(read_and_view(&unordered), (false, true));
+		let empty = TrieLayer { keys: vec![(1, 2), (2, 2), (3, 5)], vals: leaves(5) };
+		assert_eq!(read_and_view(&empty), (false, true));
+		let zero = TrieLayer { keys: vec![(1, 2)], vals: vec![(0, 1), (1, 0)] };
+		assert_eq!(read_and_view(&zero), (false, true));
+	}
+
+	#[test]
+	fn pointer_width_round_trip() {
+		let trie = TrieLayer::<u64, Vec<(usize, isize)>>::from_unordered(vec![(1, (2, -3)), (4, (5, 6))].into_iter());
+		let mut bytes = Vec::new();
+		write(&trie, &mut bytes).unwrap();
+		let back = read::<TrieLayer<u64, Vec<(usize, isize)>>>(&bytes[..]).unwrap();
+		assert_eq!(back.keys, trie.keys);
+		assert_eq!(back.vals, trie.vals);
+	}
+
+	#[test]
+	fn unaligned_owned_read() {
+		let trie = TrieLayer::<u32, Vec<(u64, i64)>>::from_unordered((0 .. 10).map(|x| (x, (x as u64, 1))));
+		let mut bytes = vec![0u8];
+		write(&trie, &mut bytes).unwrap();
+		let back = read::<TrieLayer<u32, Vec<(u64, i64)>>>(&bytes[1 ..]).unwrap();
+		assert_eq!(back.keys, trie.keys);
+		assert_eq!(back.vals, trie.vals);
+	}
+
+	#[test]
+	fn malformed_columns() {
+		assert_eq!(isize::decode(isize::encode(-7)), Some(-7));
+		assert!(read_encoded::<u64>(&mut &[0u8; 4][..], 1).is_err());
+		if ::std::mem::size_of::<usize>() < 8 {
+			let large = (1u64 << 40).to_ne_bytes();
+			assert!(read_encoded::<usize>(&mut &large[..], 1).is_err());
+		}
+	}
+}
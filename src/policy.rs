@@ -0,0 +1,302 @@
+//! Policies deciding which tries an arbor merges.
+//!
+//! An arbor holds its tries from oldest to newest, and after each append asks its policy
+//! whether to merge a run of adjacent tries. Merging eagerly keeps few tries, so that
+//! cursors and lookups consult fewer of them, at the cost of copying each tuple more
+//! often as it moves into ever larger tries. Merging lazily copies tuples less often,
+//! but leaves more tries to consult.
+//!
+//! A policy may be any type implementing `MergePolicy`, including closures from the
+//! sizes of the tries to the run to merge.
+
+use std::ops::Range;
+
+/// Selects runs of adjacent tries to merge.
+pub trait MergePolicy {
+	/// Selects a run of at least two adjacent tries to merge, or `None` if none should be.
+	///
+	/// The sizes of the tries are their numbers of tuples, from oldest to newest.
+	fn select(&self, sizes: &[usize]) -> Option<Range<usize>>;
+}
+
+impl<F: Fn(&[usize]) -> Option<Range<usize>>> MergePolicy for F {
+	fn select(&self, sizes: &[usize]) -> Option<Range<usize>> {
+		self(sizes)
+	}
+}
+
+/// Merges the newest trie into the trie before it while their sizes are within a factor of `ratio`.
+///
+/// The newest two tries are merged when `ratio` times the size of the newer exceeds the
+/// size of the older, and the merged trie is then compared with the trie before it, so
+/// that merges cascade from the newest trie. Only the newest pair is ever considered.
+/// This is the policy of `Arbor::new()` and `ArborIndex::new()`, with a ratio of two.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Cascade {
+	pub ratio: usize,
+}
+
+impl Cascade {
+	/// Creates a policy cascading merges of tries within a factor of `ratio`, which must be positive.
+	pub fn new(ratio: usize) -> Cascade {
+		assert!(ratio > 0, "size ratio must be positive");
+		Cascade { ratio: ratio }
+	}
+}
+
+impl Default for Cascade {
+	fn default() -> Cascade {
+		Cascade::new(2)
+	}
+}
+
+impl MergePolicy for Cascade {
+	fn select(&self, sizes: &[usize]) -> Option<Range<usize>> {
+		let len = sizes.len();
+		if len > 1 && sizes[len - 1].saturating_mul(self.ratio) > sizes[len - 2] {
+			Some(len - 2 .. len)
+		}
+		else {
+			None
+		}
+	}
+}
+
+/// Merges pairs of adjacent tries whose sizes are within a factor of `ratio`.
+///
+/// A pair is merged when `ratio` times the size of the newer trie exceeds the size of
+/// the older. Tries then grow geometrically in size from newest to oldest, so that there
+/// are logarithmically many of them, and each tuple is merged a logarithmic number of
+/// times. Larger ratios merge more eagerly. The default ratio is two.
+///
+/// Unlike `Cascade`, any pair may be merged and not only the newest, so that tries left
+/// out of proportion by earlier merges are merged once they qualify.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct SizeRatio {
+	pub ratio: usize,
+}
+
+impl SizeRatio {
+	/// Creates a policy merging tries within a factor of `ratio`, which must be positive.
+	pub fn new(ratio: usize) -> SizeRatio {
+		assert!(ratio > 0, "size ratio must be positive");
+		SizeRatio { ratio: ratio }
+	}
+}
+
+impl Default for SizeRatio {
+	fn default() -> SizeRatio {
+		SizeRatio::new(2)
+	}
+}
+
+impl MergePolicy for SizeRatio {
+	fn select(&self, sizes: &[usize]) -> Option<Range<usize>> {
+		// the newest qualifying pair is merged first.
+		for index in (1 .. sizes.len()).rev() {
+			if sizes[index].saturating_mul(self.ratio) > sizes[index - 1] {
+				return Some(index - 1 .. index + 1);
+			}
+		}
+		None
+	}
+}
+
+/// Merges only to keep at most `tries` tries, merging the smallest adjacent pair.
+///
+/// Nothing is merged until the limit is exceeded, which bounds the number of tries to
+/// consult while copying tuples rarely for small limits. As the limit is approached new
+/// tuples are merged into the smallest tries again and again, so small limits suit
+/// workloads with few appends.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct MaxTries {
+	pub tries: usize,
+}
+
+impl MaxTries {
+	/// Creates a policy keeping at most `tries` tries, which must be positive.
+	pub fn new(tries: usize) -> MaxTries {
+		assert!(tries > 0, "trie limit must be positive");
+		MaxTries { tries: tries }
+	}
+}
+
+impl MergePolicy for MaxTries {
+	fn select(&self, sizes: &[usize]) -> Option<Range<usize>> {
+		if sizes.len() > self.tries {
+			// the smallest pair, preferring newer pairs on ties.
+			let mut best = sizes.len() - 1;
+			for index in (1 .. sizes.len()).rev() {
+				if sizes[index - 1] + sizes[index] < sizes[best - 1] + sizes[best] {
+					best = index;
+				}
+			}
+			Some(best - 1 .. best + 1)
+		}
+		else {
+			None
+		}
+	}
+}
+
+/// Merges tries in tiers, merging `fanout` tries of a tier together at once.
+///
+/// A trie's tier is the number of times its size can be divided by `fanout`. Once the
+/// newest tries include a run of `fanout` tries of the same tier they are all merged
+/// together, forming a trie of about the next tier. Each tuple is merged once per tier,
+/// fewer times than with pairwise merges, while up to `fanout - 1` tries may be kept in
+/// each tier.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Tiered {
+	pub fanout: usize,
+}
+
+impl Tiered {
+	/// Creates a policy merging `fanout` tries at once, which must be at least two.
+	pub fn new(fanout: usize) -> Tiered {
+		assert!(fanout > 1, "fanout must be at least two");
+		Tiered { fanout: fanout }
+	}
+
+	// the tier of a trie with `size` tuples.
+	fn tier(&self, mut size: usize) -> usize {
+		let mut tier = 0;
+		while size >= self.fanout {
+			size /= self.fanout;
+			tier += 1;
+		}
+		tier
+	}
+}
+
+impl MergePolicy for Tiered {
+	fn select(&self, sizes: &[usize]) -> Option<Range<usize>> {
+		// runs of tries of the same tier, from the newest.
+		let mut upper = sizes.len();
+		while upper > 0 {
+			let tier = self.tier(sizes[upper - 1]);
+			let mut lower = upper - 1;
+			while lower > 0 && self.tier(sizes[lower - 1]) == tier {
+				lower -= 1;
+			}
+			if upper - lower >= self.fanout {
+				return Some(lower .. upper);
+			}
+			upper = lower;
+		}
+		None
+	}
+}
+
+#[cfg(test)]
+mod tests {
+
+	use testing::random;
+	use super::{MergePolicy, Cascade, SizeRatio, MaxTries, Tiered};
+
+	// merges the runs `policy` selects until it selects none, as an arbor's complete merges would.
+	fn settle<P: MergePolicy>(policy: &P, sizes: &mut Vec<usize>) {
+		while let Some(run) = policy.select(&sizes[..]) {
+			assert!(run.start + 1 < run.end && run.end <= sizes.len(), "invalid merge run: {:?}", run);
+			let merged = sizes.drain(run.clone()).sum();
+			sizes.insert(run.start, merged);
+		}
+	}
+
+	// the sizes of tries after appending `appends`, settling after each append.
+	fn simulate<P: MergePolicy, F: Fn(&[usize])>(policy: &P, appends: &[usize], check: F) -> Vec<usize> {
+		let mut sizes = Vec::new();
+		for &size in appends {
+			sizes.push(size);
+			settle(policy, &mut sizes);
+			check(&sizes[..]);
+		}
+		sizes
+	}
+
+	fn appends(seed: u64) -> Vec<usize> {
+		let mut seed = seed;
+		(0 .. 500).map(|_| 1 + (random(&mut seed) % 100) as usize).collect()
+	}
+
+	#[test]
+	fn cascade_matches_pairwise_appends() {
+		// the cascade of merges an arbor performed on each append before policies existed.
+		let mut tries: Vec<usize> = Vec::new();
+		let mut expected = Vec::new();
+		for &size in &appends(1) {
+			tries.push(size);
+			while tries.len() > 1 && tries[tries.len() - 1] > tries[tries.len() - 2] / 2 {
+				let merged = tries.pop().unwrap() + tries.pop().unwrap();
+				tries.push(merged);
+			}
+			expected.push(tries.clone());
+		}
+
+		let mut sizes = Vec::new();
+		for (&size, expected) in appends(1).iter().zip(expected) {
+			sizes.push(size);
+			settle(&Cascade::default(), &mut sizes);
+			assert_eq!(sizes, expected);
+		}
+	}
+
+	#[test]
+	fn cascade_considers_only_the_newest_pair() {
+		assert_eq!(Cascade::default().select(&[]), None);
+		assert_eq!(Cascade::default().select(&[5]), None);
+		assert_eq!(Cascade::default().select(&[4, 3, 1]), None);
+		assert_eq!(Cascade::default().select(&[4, 3, 2]), Some(1 .. 3));
+		assert_eq!(SizeRatio::default().select(&[4, 3, 1]), Some(0 .. 2));
+		assert_eq!(Cascade::new(4).select(&[4, 3, 1]), Some(1 .. 3));
+	}
+
+	#[test]
+	fn size_ratio_keeps_sizes_geometric() {
+		simulate(&SizeRatio::default(), &appends(2), |sizes| {
+			for pair in sizes.windows(2) {
+				assert!(2 * pair[1] <= pair[0], "sizes out of proportion: {:?}", sizes);
+			}
+		});
+	}
+
+	#[test]
+	fn max_tries_bounds_tries() {
+		assert_eq!(MaxTries::new(3).select(&[9, 1, 8]), None);
+		assert_eq!(MaxTries::new(3).select(&[9, 1, 8, 2]), Some(1 .. 3));
+		assert_eq!(MaxTries::new(3).select(&[9, 4, 4, 4]), Some(2 .. 4));
+		for &tries in &[1, 2, 5] {
+			let sizes = simulate(&MaxTries::new(tries), &appends(3), |sizes| {
+				assert!(sizes.len() <= tries, "more than {} tries: {:?}", tries, sizes);
+			});
+			assert_eq!(sizes.len(), tries);
+			assert_eq!(sizes.iter().sum::<usize>(), appends(3).iter().sum::<usize>());
+		}
+	}
+
+	#[test]
+	fn tiered_merges_full_tiers() {
+		let policy = Tiered::new(3);
+		assert_eq!(policy.select(&[30, 1, 2]), None);
+		assert_eq!(policy.select(&[30, 1, 2, 1]), Some(1 .. 4));
+		assert_eq!(policy.select(&[9, 10, 20, 1]), Some(0 .. 3));
+		simulate(&policy, &appends(4), |sizes| {
+			// fewer than `fanout` adjacent tries share a tier.
+			for run in sizes.windows(3) {
+				let tiers: Vec<usize> = run.iter().map(|&size| policy.tier(size)).collect();
+				assert!(tiers[0] != tiers[1] || tiers[1] != tiers[2], "full tier in {:?}", sizes);
+			}
+		});
+	}
+
+	#[test]
+	fn closures_select_runs() {
+		// merges all tries whenever there are more than four.
+		let policy = |sizes: &[usize]| if sizes.len() > 4 { Some(0 .. sizes.len()) } else { None };
+		let sizes = simulate(&policy, &[1; 12], |sizes| assert!(sizes.len() <= 4));
+		assert_eq!(sizes, vec![9, 1, 1, 1]);
+
+		let never = |_: &[usize]| None;
+		assert_eq!(simulate(&never, &[3; 7], |_| ()), vec![3; 7]);
+	}
+}
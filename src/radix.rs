@@ -0,0 +1,128 @@
+//! Radix sorting of tuples by integer keys.
+//!
+//! Building a trie from unordered tuples is dominated by sorting them. When top-level
+//! keys are integers, a least-significant-digit radix sort orders the tuples in a few
+//! linear passes rather than with a logarithmic number of comparisons for each tuple.
+//! `TrieStorage::from_unordered_radix` sorts by top-level keys this way, and then sorts
+//! the tuples of each key by comparison.
+//!
+//! Keys are sorted by their images as `u64` values, one byte at a time. Bytes in which
+//! every image agrees are skipped, so that small keys take fewer passes.
+
+/// Keys that map to `u64` values in the same order, so that they may be radix sorted.
+pub trait Radix : Ord {
+	/// The key's image, ordered as the key is.
+	fn radix(&self) -> u64;
+}
+
+macro_rules! implement_unsigned {
+	($($t:ty),*) => {
+		$(
+			impl Radix for $t {
+				fn radix(&self) -> u64 { *self as u64 }
+			}
+		)*
+	}
+}
+
+// signed keys flip their sign bit, so that negative keys precede positive keys.
+macro_rules! implement_signed {
+	($($t:ty),*) => {
+		$(
+			impl Radix for $t {
+				fn radix(&self) -> u64 { (*self as i64 as u64) ^ (1 << 63) }
+			}
+		)*
+	}
+}
+
+implement_unsigned!(u8, u16, u32, u64, usize);
+implement_signed!(i8, i16, i32, i64, isize);
+
+/// Sorts `items` stably by the images `key` gives them.
+///
+/// The images are sorted with the positions of their items, which are then moved into
+/// place once, so that items are not moved by each pass.
+pub fn sort_by_radix<T, F: Fn(&T) -> u64>(items: &mut Vec<T>, key: F) {
+
+	let mut keyed: Vec<(u64, usize)> = items.iter().enumerate().map(|(index, item)| (key(item), index)).collect();
+
+	// the number of images with each value of each byte.
+	let mut counts = vec![[0usize; 256]; 8];
+	for &(image, _) in &keyed {
+		for (byte, counts) in counts.iter_mut().enumerate() {
+			counts[((image >> (8 * byte)) & 255) as usize] += 1;
+		}
+	}
+
+	let mut buffer = vec![(0, 0); keyed.len()];
+	for (byte, counts) in counts.iter().enumerate() {
+		if counts.contains(&keyed.len()) {
+			continue;
+		}
+		let mut starts = [0usize; 256];
+		let mut sum = 0;
+		for (start, &count) in starts.iter_mut().zip(counts.iter()) {
+			*start = sum;
+			sum += count;
+		}
+		for &(image, index) in &keyed {
+			let digit = ((image >> (8 * byte)) & 255) as usize;
+			buffer[starts[digit]] = (image, index);
+			starts[digit] += 1;
+		}
+		::std::mem::swap(&mut keyed, &mut buffer);
+	}
+
+	let mut slots: Vec<Option<T>> = items.drain(..).map(Some).collect();
+	items.extend(keyed.iter().map(|&(_, index)| slots[index].take().unwrap()));
+}
+
+#[cfg(test)]
+mod tests {
+
+	use TrieStorage;
+	use trie::TrieLayer;
+	use testing::{random, triples};
+	use super::{Radix, sort_by_radix};
+
+	#[test]
+	fn images_preserve_order() {
+		let signed = [i64::min_value(), -1000, -1, 0, 1, 255, 256, i64::max_value()];
+		assert!(signed.windows(2).all(|x| x[0].radix() < x[1].radix()));
+		let small = [i8::min_value(), -1, 0, i8::max_value()];
+		assert!(small.windows(2).all(|x| x[0].radix() < x[1].radix()));
+		let unsigned = [0u64, 1, 255, 256, 1 << 40, u64::max_value()];
+		assert!(unsigned.windows(2).all(|x| x[0].radix() < x[1].radix()));
+	}
+
+	#[test]
+	fn sorts_stably() {
+		let mut seed = 15;
+		for &(count, range) in &[(0, 1), (1, 1), (100, 10), (1000, 1 << 20), (1000, 1 << 62)] {
+			let mut items: Vec<(i64, usize)> = (0 .. count).map(|index| {
+				let key = (random(&mut seed) << 31 | random(&mut seed)) % range;
+				(key as i64 - range as i64 / 2, index)
+			}).collect();
+			let mut expected = items.clone();
+			expected.sort_by_key(|x| x.0);
+			sort_by_radix(&mut items, |x| x.0.radix());
+			assert_eq!(items, expected);
+		}
+	}
+
+	#[test]
+	fn agrees_with_comparison() {
+		type Trie = TrieLayer<u32, TrieLayer<u32, Vec<(u32, i64)>>>;
+		let mut seed = 16;
+		for round in 0 .. 10 {
+			let tuples = triples(&mut seed, 1 + 100 * round, 1 + 50 * round as u32, 3);
+			let sorted = Trie::from_unordered(tuples.clone().into_iter());
+			let radix = Trie::from_unordered_radix(tuples.into_iter());
+			assert!(radix.validate().is_ok());
+			assert_eq!(radix.keys, sorted.keys);
+			assert_eq!(radix.vals.keys, sorted.vals.keys);
+			assert_eq!(radix.vals.vals, sorted.vals.vals);
+		}
+	}
+}
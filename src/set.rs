@@ -0,0 +1,305 @@
+//! Leaf storage for tries of sets, without differences.
+//!
+//! A `KeySet` is a sorted list of distinct keys, for relations that only record the
+//! presence of tuples. Unlike a `Vec<(K, R)>` leaf it stores no difference for each
+//! key, and when tries are merged keys present in both are kept once. Its cursors
+//! present each key with the value `()`.
+//!
+//! A `KeySet` is a newtype around `Vec<K>`, rather than `Vec<K>` itself, so that it
+//! does not collide with the `Vec<(K, R)>` leaf implementations.
+
+use std::io::{self, Write};
+use std::iter::Once;
+
+use {TrieStorage, TrieRef};
+use trie::{Cursor, BackwardCursor, Violation, validate_order, advance, retreat, fueled_step};
+use consolidate::Consolidate;
+use flatten::Flatten;
+use persist::{self, Plain, Encode, Persist, View};
+
+/// A sorted list of distinct keys, used as the leaf of a trie.
+#[derive(Debug, PartialEq, Eq)]
+pub struct KeySet<K> {
+	pub keys: Vec<K>,
+}
+
+impl<K: Ord+Clone> TrieStorage for KeySet<K> {
+	type Item = K;
+	type Key = K;
+	fn new() -> Self { KeySet { keys: vec![] } }
+	fn with_capacity(other1: &Self, other2: &Self) -> Self {
+		KeySet { keys: Vec::with_capacity(other1.keys.len() + other2.keys.len()) }
+	}
+	fn keys(&self) -> usize { self.keys.len() }
+	fn tuples(&self) -> usize { self.keys.len() }
+	fn tuples_in(&self, lower: usize, upper: usize) -> usize { upper - lower }
+	fn key(&self, index: usize) -> &K { &self.keys[index] }
+	fn extend_trie(&mut self, other: &Self, lower: usize, upper: usize) {
+		debug_assert!(lower < upper);
+		self.keys.extend_from_slice(&other.keys[lower .. upper]);
+	}
+	fn extend_merge_fueled(&mut self, other1: (&Self, &mut usize, usize), other2: (&Self, &mut usize, usize), fuel: &mut usize) {
+
+		let (set1, lower1, upper1) = other1;
+		let (set2, lower2, upper2) = other2;
+
+		self.keys.reserve(upper1 + upper2 - *lower1 - *lower2);
+
+		while *lower1 < upper1 && *lower2 < upper2 && *fuel > 0 {
+			match set1.keys[*lower1].cmp(&set2.keys[*lower2]) {
+				::std::cmp::Ordering::Less => {
+					let step = 1 + advance(&set1.keys[(1 + *lower1)..upper1], |x| x < &set2.keys[*lower2]);
+					let (step, tuples) = fueled_step(set1, *lower1, step, *fuel);
+					self.extend_trie(set1, *lower1, *lower1 + step);
+					*lower1 += step;
+					*fuel = fuel.saturating_sub(tuples);
+				}
+				::std::cmp::Ordering::Equal => {
+					// keys present in both sets are kept once.
+					self.keys.push(set1.keys[*lower1].clone());
+					*lower1 += 1;
+					*lower2 += 1;
+					*fuel = fuel.saturating_sub(2);
+				}
+				::std::cmp::Ordering::Greater => {
+					let step = 1 + advance(&set2.keys[(1 + *lower2)..upper2], |x| x < &set1.keys[*lower1]);
+					let (step, tuples) = fueled_step(set2, *lower2, step, *fuel);
+					self.extend_trie(set2, *lower2, *lower2 + step);
+					*lower2 += step;
+					*fuel = fuel.saturating_sub(tuples);
+				}
+			}
+		}
+	}
+	fn extend_merge_equal(&mut self, heads: &[(&Self, usize)]) {
+		self.keys.push(heads[0].0.keys[heads[0].1].clone());
+	}
+	fn extend_tuple(&mut self, key: K, is_new: bool) {
+		debug_assert!(is_new || self.keys.last().map(|x| x <= &key).unwrap_or(true), "tuples pushed out of order");
+		// repeated keys under the same parent key are kept once.
+		if is_new || self.keys.last() != Some(&key) {
+			self.keys.push(key);
+		}
+	}
+	fn validate_layer(&self, ends: &[usize], depth: usize) -> Result<(), Violation> {
+		validate_order(&self.keys[..], |x| x, ends, depth)
+	}
+	fn cmp_items(item1: &K, item2: &K) -> ::std::cmp::Ordering {
+		item1.cmp(item2)
+	}
+	fn add_items(_item1: &mut K, _item2: K) { }
+	fn is_zero_item(_item: &K) -> bool { false }
+	fn item_key(item: &K) -> &K { item }
+}
+
+impl<'a, K: Ord+'a> TrieRef<'a> for KeySet<K> {
+	type Cursor = SetCursor<'a, K>;
+	fn keys_cnt(&self) -> usize { self.keys.len() }
+	fn cursor(&'a self, lower: usize, upper: usize) -> Self::Cursor {
+		SetCursor::new(&self.keys[lower .. upper])
+	}
+}
+
+/// A cursor over a range of keys in a `KeySet`.
+pub struct SetCursor<'a, K: 'a> {
+	keys: &'a [K],
+}
+
+impl<'a, K: Ord+'a> SetCursor<'a, K> {
+	pub fn new(keys: &'a [K]) -> SetCursor<'a, K> {
+		SetCursor { keys: keys }
+	}
+}
+
+impl<'a, K: Ord+'a> Cursor<'a> for SetCursor<'a, K> {
+	type Key = K;
+	type Val = ();
+
+	fn next(&mut self) -> Option<(&'a K, ())> {
+		if !self.keys.is_empty() {
+			let result = &self.keys[0];
+			self.keys = &self.keys[1 ..];
+			Some((result, ()))
+		}
+		else {
+			None
+		}
+	}
+	#[inline(never)]
+	fn seek(&mut self, key: &K) {
+		let count = advance(self.keys, |x| x < key);
+		self.keys = &self.keys[count ..];
+	}
+	fn peek(&self) -> Option<&'a K> {
+		self.keys.first()
+	}
+	fn size(&self) -> usize {
+		self.keys.len()
+	}
+}
+
+impl<'a, K: Ord+'a> BackwardCursor<'a> for SetCursor<'a, K> {
+	fn prev(&mut self) -> Option<(&'a K, ())> {
+		if !self.keys.is_empty() {
+			let result = &self.keys[self.keys.len() - 1];
+			self.keys = &self.keys[.. self.keys.len() - 1];
+			Some((result, ()))
+		}
+		else {
+			None
+		}
+	}
+	#[inline(never)]
+	fn seek_back(&mut self, key: &K) {
+		let count = retreat(self.keys, |x| x > key);
+		self.keys = &self.keys[.. self.keys.len() - count];
+	}
+	fn peek_back(&self) -> Option<&'a K> {
+		self.keys.last()
+	}
+}
+
+impl<'a, K: 'a> Clone for SetCursor<'a, K> {
+	fn clone(&self) -> Self {
+		SetCursor { keys: self.keys }
+	}
+}
+
+impl<'a, K: Ord+'a> Consolidate<'a> for SetCursor<'a, K> {
+	type Output = ();
+	fn consolidate<I: Iterator<Item=()>>(mut vals: I) -> Option<()> {
+		vals.next()
+	}
+}
+
+/// Sets present each key once, however many cursors contain it.
+impl<'a, K: Ord+'a> Flatten<'a> for SetCursor<'a, K> {
+	type Suffix = ();
+	type Iter = Once<()>;
+	type Group = Once<()>;
+	fn flatten(_val: ()) -> Self::Iter { ::std::iter::once(()) }
+	fn group(_vals: Vec<()>) -> Self::Group { ::std::iter::once(()) }
+}
+
+impl<K: Ord+Clone+Encode> Persist for KeySet<K> {
+	fn write_columns<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+		persist::write_column(writer, &[self.keys.len() as u64])?;
+		persist::write_encoded(writer, &self.keys[..])
+	}
+	fn read_columns(bytes: &mut &[u8]) -> io::Result<Self> {
+		let len = persist::read_len(bytes)?;
+		Ok(KeySet { keys: persist::read_encoded::<K>(bytes, len)? })
+	}
+}
+
+impl<'a, K: Ord+Clone+Plain> View<'a> for KeySet<K> {
+	type View = SetView<'a, K>;
+	fn view_columns(bytes: &mut &'a [u8]) -> io::Result<Self::View> {
+		let len = persist::read_len(bytes)?;
+		Ok(SetView { keys: persist::read_column::<K>(bytes, len)? })
+	}
+}
+
+/// A read-only view of a serialized `KeySet`.
+pub struct SetView<'a, K: 'a> {
+	keys: &'a [K],
+}
+
+impl<'a, K: Ord+'a> TrieRef<'a> for SetView<'a, K> {
+	type Cursor = SetCursor<'a, K>;
+	fn keys_cnt(&self) -> usize { self.keys.len() }
+	fn cursor(&'a self, lower: usize, upper: usize) -> Self::Cursor {
+		SetCursor::new(&self.keys[lower .. upper])
+	}
+}
+
+#[cfg(test)]
+mod tests {
+
+	use std::collections::BTreeSet;
+
+	use {TrieStorage, TrieRef};
+	use trie::{Cursor, BackwardCursor, TrieLayer};
+	use persist::{Buffer, write, read, view};
+	use testing::random;
+	use super::KeySet;
+
+	type Trie = TrieLayer<u32, KeySet<u32>>;
+
+	// `count` pairs of keys and values less than ten, so that many repeat.
+	fn pairs(seed: &mut u64, count: usize) -> Vec<(u32, u32)> {
+		(0 .. count).map(|_| ((random(seed) % 10) as u32, (random(seed) % 10) as u32)).collect()
+	}
+
+	fn contents(trie: &Trie) -> Vec<(u32, u32)> {
+		trie.into_iter().map(|(key, (val, ()))| (*key, *val)).collect()
+	}
+
+	#[test]
+	fn duplicates_kept_once() {
+		let mut seed = 13;
+		for round in 0 .. 20 {
+			let pairs1 = pairs(&mut seed, 5 + 10 * round);
+			let pairs2 = pairs(&mut seed, 5 + 10 * round);
+			let trie1 = Trie::from_unordered(pairs1.clone().into_iter());
+			let trie2 = Trie::from_unordered(pairs2.clone().into_iter());
+			assert!(trie1.validate().is_ok());
+			assert_eq!(contents(&trie1), pairs1.iter().cloned().collect::<BTreeSet<_>>().into_iter().collect::<Vec<_>>());
+
+			// pairs present in both tries are kept once by their merge.
+			let mut merged = Trie::with_capacity(&trie1, &trie2);
+			merged.extend_merge((&trie1, 0, trie1.keys()), (&trie2, 0, trie2.keys()));
+			assert!(merged.validate().is_ok());
+			let expected: BTreeSet<_> = pairs1.into_iter().chain(pairs2).collect();
+			assert_eq!(contents(&merged), expected.into_iter().collect::<Vec<_>>());
+		}
+	}
+
+	#[test]
+	fn seek_both_ways() {
+		let set = KeySet { keys: vec![1, 3, 5, 7, 9] };
+		let mut cursor = set.enumerate();
+		cursor.seek(&4);
+		assert_eq!(cursor.peek(), Some(&5));
+		cursor.seek(&5);
+		assert_eq!(cursor.peek(), Some(&5));
+		cursor.seek_back(&8);
+		assert_eq!(cursor.peek_back(), Some(&7));
+		assert_eq!(cursor.size(), 2);
+		assert_eq!(cursor.prev(), Some((&7, ())));
+		assert_eq!(cursor.next(), Some((&5, ())));
+		assert_eq!(cursor.next(), None);
+
+		let mut cursor = set.cursor(1, 4);
+		cursor.seek(&0);
+		assert_eq!(cursor.peek(), Some(&3));
+		cursor.seek(&8);
+		assert_eq!(cursor.peek(), None);
+		let mut cursor = set.enumerate();
+		cursor.seek_back(&0);
+		assert_eq!(cursor.peek_back(), None);
+	}
+
+	#[test]
+	fn persist_round_trip() {
+		let mut seed = 14;
+		let trie = Trie::from_unordered(pairs(&mut seed, 100).into_iter());
+		let mut bytes = Vec::new();
+		write(&trie, &mut bytes).unwrap();
+
+		let back = read::<Trie>(&bytes[..]).unwrap();
+		assert_eq!(back.keys, trie.keys);
+		assert_eq!(back.vals, trie.vals);
+
+		let buffer = Buffer::read_from(&bytes[..]).unwrap();
+		let viewed = view::<Trie>(buffer.as_bytes()).unwrap();
+		let mut cursor = viewed.enumerate();
+		let mut found = Vec::new();
+		while let Some((key, mut vals)) = cursor.next() {
+			while let Some((val, ())) = vals.next() {
+				found.push((*key, *val));
+			}
+		}
+		assert_eq!(found, contents(&trie));
+	}
+}
@@ -0,0 +1,25 @@
+//! Deterministic test data shared by the unit tests of several modules.
+
+/// Advances `seed` and returns 31 pseudo-random bits drawn from it.
+pub fn random(seed: &mut u64) -> u64 {
+	*seed = seed.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+	*seed >> 33
+}
+
+/// `count` pairs of keys less than `keys` and values less than `vals`, with differences
+/// of both signs so that some cancel.
+pub fn pairs(seed: &mut u64, count: usize, keys: u32, vals: u32) -> Vec<(u32, (u32, i64))> {
+	(0 .. count).map(|_| {
+		let bits = random(seed);
+		let diff = if bits & (1 << 30) == 0 { 1 } else { -1 };
+		((bits % keys as u64) as u32, ((bits / keys as u64 % vals as u64) as u32, diff))
+	}).collect()
+}
+
+/// `count` triples of keys less than `keys` and of values and times less than `vals`,
+/// with differences of both signs so that some cancel.
+pub fn triples(seed: &mut u64, count: usize, keys: u32, vals: u32) -> Vec<(u32, (u32, (u32, i64)))> {
+	pairs(seed, count, keys, vals).into_iter().map(|(key, (val, diff))| {
+		(key, (val, ((random(seed) % vals as u64) as u32, diff)))
+	}).collect()
+}
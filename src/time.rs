@@ -0,0 +1,206 @@
+//! Trie layers whose keys are times, and the compaction of times by frontiers.
+//!
+//! A `TimeLayer` is a `TrieLayer` whose keys are `Lattice` elements. It behaves 
+//! identically to a `TrieLayer` for storage and navigation, but supports advancing
+//! its times by a frontier: each time is replaced by its representative, and the 
+//! values of times with the same representative are merged. A trie of `(K,T,V,W)`
+//! tuples is then a `TrieLayer<K, TimeLayer<T, Vec<(V, W)>>>`.
+
+use {TrieStorage, TrieRef};
+use trie::{TrieLayer, TrieCursor, Retract, Violation};
+use lattice::Lattice;
+
+/// Tries whose times may be advanced by a frontier.
+pub trait Advance<T: Lattice> : TrieStorage {
+	/// Extends the trie by the range of the supplied trie, with times advanced by `frontier`.
+	///
+	/// Tuples whose times advance to the same representative are consolidated, and those
+	/// whose accumulated differences are zero are discarded.
+	fn extend_advanced(&mut self, other: &Self, lower: usize, upper: usize, frontier: &[T]);
+
+	/// Returns a copy of the trie with all times advanced by `frontier`.
+	fn advance_by(&self, frontier: &[T]) -> Self {
+		let mut result = Self::new();
+		result.extend_advanced(self, 0, self.keys(), frontier);
+		result
+	}
+}
+
+/// A layer of a trie whose keys are times.
+#[derive(Debug)]
+pub struct TimeLayer<T: Ord, L> {
+	pub layer: TrieLayer<T, L>,
+}
+
+impl<T: Lattice, L: TrieStorage> TrieStorage for TimeLayer<T, L> {
+	type Item = (T, L::Item);
+	type Key = T;
+	fn new() -> Self { TimeLayer { layer: TrieLayer::new() } }
+	fn with_capacity(other1: &Self, other2: &Self) -> Self {
+		TimeLayer { layer: TrieLayer::with_capacity(&other1.layer, &other2.layer) }
+	}
+	fn keys(&self) -> usize { self.layer.keys() }
+	fn tuples(&self) -> usize { self.layer.tuples() }
+	fn tuples_in(&self, lower: usize, upper: usize) -> usize { self.layer.tuples_in(lower, upper) }
+	fn key(&self, index: usize) -> &T { self.layer.key(index) }
+	fn extend_trie(&mut self, other: &Self, lower: usize, upper: usize) {
+		self.layer.extend_trie(&other.layer, lower, upper);
+	}
+	fn extend_merge_fueled(&mut self, other1: (&Self, &mut usize, usize), other2: (&Self, &mut usize, usize), fuel: &mut usize) {
+		self.layer.extend_merge_fueled((&(other1.0).layer, other1.1, other1.2), (&(other2.0).layer, other2.1, other2.2), fuel);
+	}
+	fn extend_merge_equal(&mut self, heads: &[(&Self, usize)]) {
+		let heads: Vec<_> = heads.iter().map(|&(trie, index)| (&trie.layer, index)).collect();
+		self.layer.extend_merge_equal(&heads[..]);
+	}
+	fn extend_tuple(&mut self, tuple: Self::Item, is_new: bool) {
+		self.layer.extend_tuple(tuple, is_new);
+	}
+	fn validate_layer(&self, ends: &[usize], depth: usize) -> Result<(), Violation> {
+		self.layer.validate_layer(ends, depth)
+	}
+	fn cmp_items(item1: &Self::Item, item2: &Self::Item) -> ::std::cmp::Ordering {
+		TrieLayer::<T, L>::cmp_items(item1, item2)
+	}
+	fn add_items(item1: &mut Self::Item, item2: Self::Item) {
+		TrieLayer::<T, L>::add_items(item1, item2);
+	}
+	fn is_zero_item(item: &Self::Item) -> bool {
+		TrieLayer::<T, L>::is_zero_item(item)
+	}
+	fn item_key(item: &Self::Item) -> &T {
+		TrieLayer::<T, L>::item_key(item)
+	}
+}
+
+impl<T: Lattice, L: Retract> Retract for TimeLayer<T, L> {
+	fn negate_item(item: &mut Self::Item) {
+		L::negate_item(&mut item.1);
+	}
+}
+
+impl<'a, T: Ord+'a, L: 'a> TrieRef<'a> for TimeLayer<T, L> where L: TrieRef<'a> {
+	type Cursor = TrieCursor<'a, T, L>;
+	fn keys_cnt(&self) -> usize { self.layer.keys_cnt() }
+	fn cursor(&'a self, lower: usize, upper: usize) -> Self::Cursor {
+		self.layer.cursor(lower, upper)
+	}
+}
+
+impl<T: Lattice, L: TrieStorage> Advance<T> for TimeLayer<T, L> {
+	fn extend_advanced(&mut self, other: &Self, lower: usize, upper: usize, frontier: &[T]) {
+
+		// advance each time, and order the value ranges by their advanced times.
+		let mut advanced = Vec::with_capacity(upper - lower);
+		for index in lower .. upper {
+			let v_lower = if index == 0 { 0 } else { other.layer.keys[index-1].1 };
+			let v_upper = other.layer.keys[index].1;
+			advanced.push((other.layer.keys[index].0.advance_by(frontier), v_lower, v_upper));
+		}
+		advanced.sort_by(|x,y| x.0.cmp(&y.0));
+
+		// merge the value ranges of each distinct advanced time.
+		let mut index = 0;
+		while index < advanced.len() {
+
+			let mut next = index + 1;
+			while next < advanced.len() && advanced[next].0 == advanced[index].0 {
+				next += 1;
+			}
+
+			let v_len = self.layer.vals.keys();
+			if next - index == 1 {
+				self.layer.vals.extend_trie(&other.layer.vals, advanced[index].1, advanced[index].2);
+			}
+			else {
+				let ranges: Vec<_> = advanced[index .. next].iter().map(|&(_, v_lower, v_upper)| (&other.layer.vals, v_lower, v_upper)).collect();
+				self.layer.vals.extend_merge_many(&ranges[..]);
+			}
+
+			if self.layer.vals.keys() > v_len {
+				self.layer.keys.push((advanced[index].0.clone(), self.layer.vals.keys()));
+			}
+
+			index = next;
+		}
+	}
+}
+
+impl<T: Lattice, K: Ord+Clone, L: Advance<T>> Advance<T> for TrieLayer<K, L> {
+	fn extend_advanced(&mut self, other: &Self, lower: usize, upper: usize, frontier: &[T]) {
+		for index in lower .. upper {
+			let v_lower = if index == 0 { 0 } else { other.keys[index-1].1 };
+			let v_upper = other.keys[index].1;
+
+			// record vals_length so we can tell if anything was pushed.
+			let v_len = self.vals.keys();
+			self.vals.extend_advanced(&other.vals, v_lower, v_upper, frontier);
+			if self.vals.keys() > v_len {
+				self.keys.push((other.keys[index].0.clone(), self.vals.keys()));
+			}
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+
+	use std::collections::BTreeMap;
+
+	use TrieStorage;
+	use trie::TrieLayer;
+	use lattice::Lattice;
+	use testing::{random, pairs};
+	use super::{TimeLayer, Advance};
+
+	type Trie = TrieLayer<u32, TimeLayer<(u32, u32), Vec<(u32, i64)>>>;
+
+	fn contents(trie: &Trie) -> Vec<(u32, (u32, u32), u32, i64)> {
+		trie.into_iter().map(|(key, (time, (val, diff)))| (*key, *time, *val, *diff)).collect()
+	}
+
+	#[test]
+	fn advance_consolidates_cancelling_differences() {
+		let trie = Trie::from_unordered(vec![
+			(1, ((1, 0), (5, 1))),
+			(1, ((0, 1), (5, -1))),
+			(1, ((0, 0), (6, 2))),
+			(2, ((1, 0), (5, 1))),
+			(2, ((0, 1), (5, 1))),
+		].into_iter());
+		let advanced = trie.advance_by(&[(1, 1)]);
+		assert!(advanced.validate().is_ok());
+		assert_eq!(contents(&advanced), vec![(1, (1, 1), 6, 2), (2, (1, 1), 5, 2)]);
+
+		// advancing again by the same frontier changes nothing.
+		assert_eq!(contents(&advanced.advance_by(&[(1, 1)])), contents(&advanced));
+
+		// keys all of whose differences cancel are dropped, as are their times.
+		let trie = Trie::from_unordered(vec![(3, ((0, 2), (7, 1))), (3, ((2, 0), (7, -1)))].into_iter());
+		let advanced = trie.advance_by(&[(2, 2)]);
+		assert!(advanced.validate().is_ok());
+		assert_eq!(advanced.keys(), 0);
+		assert_eq!(advanced.vals.keys(), 0);
+	}
+
+	#[test]
+	fn advance_agrees_with_model() {
+		let mut seed = 9;
+		for _ in 0 .. 20 {
+			let tuples: Vec<_> = pairs(&mut seed, 300, 20, 4).into_iter().map(|(key, (val, diff))| {
+				(key, (((random(&mut seed) % 4) as u32, (random(&mut seed) % 4) as u32), (val, diff)))
+			}).collect();
+			let frontier = [((random(&mut seed) % 4) as u32, 1), (1, (random(&mut seed) % 4) as u32)];
+
+			let mut model = BTreeMap::new();
+			for &(key, (time, (val, diff))) in &tuples {
+				*model.entry((key, time.advance_by(&frontier), val)).or_insert(0) += diff;
+			}
+			let expected: Vec<_> = model.into_iter().filter(|x| x.1 != 0).map(|((key, time, val), diff)| (key, time, val, diff)).collect();
+
+			let advanced = Trie::from_unordered(tuples.into_iter()).advance_by(&frontier);
+			assert!(advanced.validate().is_ok());
+			assert_eq!(contents(&advanced), expected);
+		}
+	}
+}
@@ -1,5 +1,7 @@
 //! Traits and types supporting general tuple trie implementations.
 
+use radix::{self, Radix};
+
 /// An iterator replacement for ordered sequences with random access.
 ///
 /// Types implementing `Cursor` are able to both iterate through elements in
@@ -23,6 +25,19 @@ pub trait Cursor<'a> {
 	fn size(&self) -> usize;
 }
 
+/// A cursor that may also be consumed from its end.
+///
+/// The methods mirror the forward methods of `Cursor`, and the remaining items are
+/// those not yet consumed from either end.
+pub trait BackwardCursor<'a> : Cursor<'a> {
+	/// Retreats the cursor and returns the last remaining item.
+	fn prev(&mut self) -> Option<(&'a Self::Key, Self::Val)>;
+	/// Retreats the cursor to the last element with key less or equal to `key`.
+	fn seek_back(&mut self, key: &Self::Key);
+	/// Returns the key of the last remaining item, if one exists.
+	fn peek_back(&self) -> Option<&'a Self::Key>;
+}
+
 /// A reference to a trie, capable of enumerating ranges of values.
 pub trait TrieRef<'a> : 'a {
 	/// The type of cursor the trie reference uses to navigate its elements.
@@ -41,6 +56,8 @@ pub trait TrieRef<'a> : 'a {
 pub trait TrieStorage : Sized {
 	/// Type of the item stored in the trie.
 	type Item;
+	/// Type of the keys at this level of the trie.
+	type Key: Ord;
 	/// Allocates a new empty trie.
 	fn new() -> Self;
 	/// Allocates a new empty trie sized to hold both `other1` and `other2`.
@@ -49,19 +66,133 @@ pub trait TrieStorage : Sized {
 	fn keys(&self) -> usize;
 	/// Reports the number of tuples in the trie.
 	fn tuples(&self) -> usize;
+	/// Reports the number of tuples beneath the keys from `lower` to `upper`.
+	fn tuples_in(&self, lower: usize, upper: usize) -> usize;
+	/// Returns the key at `index` at this level.
+	fn key(&self, index: usize) -> &Self::Key;
 	/// Extends the trie by the range of the supplied trie.
 	fn extend_trie(&mut self, other: &Self, lower: usize, upper: usize);
 	/// Merges two other tries, with supplied lower and upper indices, into this trie.
-	fn extend_merge(&mut self, other1: (&Self, usize, usize), other2: (&Self, usize, usize));
+	fn extend_merge(&mut self, other1: (&Self, usize, usize), other2: (&Self, usize, usize)) {
+		let (trie1, mut lower1, upper1) = other1;
+		let (trie2, mut lower2, upper2) = other2;
+		let mut fuel = usize::MAX;
+		self.extend_merge_fueled((trie1, &mut lower1, upper1), (trie2, &mut lower2, upper2), &mut fuel);
+		if lower1 < upper1 { self.extend_trie(trie1, lower1, upper1); }
+		if lower2 < upper2 { self.extend_trie(trie2, lower2, upper2); }
+	}
+	/// Merges two other tries, while both ranges are non-empty and `fuel` remains.
+	///
+	/// Each lower index is advanced past the keys merged from its trie, and `fuel` is 
+	/// reduced by the number of tuples beneath those keys. Once either range is exhausted
+	/// the method returns, leaving the remainder of the other range for the caller to copy.
+	///
+	/// Runs of keys are copied only as far as their tuples fit in `fuel`, though at least
+	/// one key is always copied, and the values of a key found in both ranges are merged
+	/// at once. The tuples consumed may so exceed `fuel` by those beneath a single key.
+	fn extend_merge_fueled(&mut self, other1: (&Self, &mut usize, usize), other2: (&Self, &mut usize, usize), fuel: &mut usize);
+	/// Merges any number of other tries, with supplied lower and upper indices, into this trie.
+	///
+	/// The merge takes a single pass over the inputs at each level. Runs of keys found in
+	/// only one input are copied with `extend_trie`, and keys found in several inputs are
+	/// merged with `extend_merge_equal`.
+	fn extend_merge_many(&mut self, others: &[(&Self, usize, usize)]) {
+		let mut lowers: Vec<usize> = others.iter().map(|x| x.1).collect();
+		let mut holders = Vec::with_capacity(others.len());
+		let mut heads = Vec::with_capacity(others.len());
+		while let Some(upper) = least_heads(others, &lowers[..], &mut holders) {
+			if holders.len() == 1 {
+				let index = holders[0];
+				self.extend_trie(others[index].0, lowers[index], upper);
+				lowers[index] = upper;
+			}
+			else {
+				heads.clear();
+				heads.extend(holders.iter().map(|&index| (others[index].0, lowers[index])));
+				self.extend_merge_equal(&heads[..]);
+				for &index in &holders {
+					lowers[index] += 1;
+				}
+			}
+		}
+	}
+	/// Merges the keys at the supplied indices of several tries, which are all equal.
+	///
+	/// Nothing is pushed if the merged key has no values, or a zero difference.
+	fn extend_merge_equal(&mut self, heads: &[(&Self, usize)]);
+
 	/// Pushes one tuple on; used for trie construction.
+	///
+	/// Tuples must be pushed in order. Out of order tuples are rejected in debug builds.
 	fn extend_tuple(&mut self, tuple: Self::Item, is_new: bool);
 
+	/// Checks the trie's invariants, and reports the first violation found.
+	///
+	/// Keys must strictly increase within the range of each parent key, offsets must 
+	/// increase and end at the number of keys in the layer beneath, every key must have
+	/// values, and no leaf may have a zero difference.
+	fn validate(&self) -> Result<(), Violation> {
+		let keys = self.keys();
+		self.validate_layer(&[keys], 0)
+	}
+	/// Checks this layer and those beneath it, where each parent key at `depth - 1` has 
+	/// a range of keys ending at the corresponding element of `ends`.
+	fn validate_layer(&self, ends: &[usize], depth: usize) -> Result<(), Violation>;
+
+	/// Compares two items by their keys at every level, ignoring any difference.
+	fn cmp_items(item1: &Self::Item, item2: &Self::Item) -> ::std::cmp::Ordering;
+	/// Accumulates the difference of `item2` into `item1`, whose keys are equal.
+	fn add_items(item1: &mut Self::Item, item2: Self::Item);
+	/// Returns true if the item's difference is zero, and it may be discarded.
+	fn is_zero_item(item: &Self::Item) -> bool;
+	/// Returns the top-level key of an item.
+	fn item_key(item: &Self::Item) -> &Self::Key;
+
 	fn merge(&self, other: &Self) -> Self {
 		let mut result = Self::with_capacity(self, other);
 		result.extend_merge((self, 0, self.keys()), (other, 0, other.keys()));
 		result
 	}
 
+	/// Merges several tries into a new trie.
+	fn merge_many(tries: &[&Self]) -> Self {
+		let ranges: Vec<_> = tries.iter().map(|&trie| (trie, 0, trie.keys())).collect();
+		let mut result = Self::new();
+		result.extend_merge_many(&ranges[..]);
+		result
+	}
+
+	/// Returns the index of the first key in `[lower, upper)` at least `key`, or `upper`.
+	///
+	/// As with `advance`, this uses an exponential search and takes a number of steps
+	/// logarithmic in the distance from `lower` to the result.
+	fn lower_bound(&self, key: &Self::Key, lower: usize, upper: usize) -> usize {
+
+		let mut index = lower;
+		if index < upper && self.key(index) < key {
+
+			// advance in exponentially growing steps.
+			let mut step = 1;
+			while index + step < upper && self.key(index + step) < key {
+				index += step;
+				step <<= 1;
+			}
+
+			// advance in exponentially shrinking steps.
+			step >>= 1;
+			while step > 0 {
+				if index + step < upper && self.key(index + step) < key {
+					index += step;
+				}
+				step >>= 1;
+			}
+
+			index += 1;
+		}
+
+		index
+	}
+
 	/// Creates a new trie from an ordered sequence of items.
 	fn from_ordered<I: Iterator<Item=Self::Item>>(iter: I) -> Self {
 		let mut result = Self::new();
@@ -70,8 +201,181 @@ pub trait TrieStorage : Sized {
 		}
 		result
 	}
+
+	/// Creates a new trie from a sequence of items in any order.
+	///
+	/// The items are sorted, and items with equal keys are consolidated by accumulating 
+	/// their differences. Items whose accumulated differences are zero are discarded.
+	fn from_unordered<I: Iterator<Item=Self::Item>>(iter: I) -> Self {
+		let mut items: Vec<Self::Item> = iter.collect();
+		items.sort_by(Self::cmp_items);
+		from_sorted(items)
+	}
+
+	/// Creates a new trie from a sequence of items in any order, as `from_unordered` does,
+	/// but radix sorting the items by their integer top-level keys.
+	///
+	/// The items of each top-level key are then sorted by comparison, which is cheap when
+	/// each key has few items.
+	fn from_unordered_radix<I: Iterator<Item=Self::Item>>(iter: I) -> Self where Self::Key: Radix {
+		let mut items: Vec<Self::Item> = iter.collect();
+		radix::sort_by_radix(&mut items, |item| Self::item_key(item).radix());
+		let mut lower = 0;
+		while lower < items.len() {
+			let upper = lower + 1 + items[lower + 1 ..].iter().take_while(|item| Self::item_key(item) == Self::item_key(&items[lower])).count();
+			if upper - lower > 1 {
+				items[lower .. upper].sort_by(Self::cmp_items);
+			}
+			lower = upper;
+		}
+		from_sorted(items)
+	}
 }	
 
+// a trie of sorted `items`, consolidating items with equal keys and discarding those
+// whose accumulated differences are zero.
+fn from_sorted<T: TrieStorage>(items: Vec<T::Item>) -> T {
+	let mut result = T::new();
+	let mut items = items.into_iter();
+	if let Some(mut current) = items.next() {
+		for item in items {
+			if T::cmp_items(&current, &item) == ::std::cmp::Ordering::Equal {
+				T::add_items(&mut current, item);
+			}
+			else {
+				if !T::is_zero_item(&current) {
+					result.extend_tuple(current, false);
+				}
+				current = item;
+			}
+		}
+		if !T::is_zero_item(&current) {
+			result.extend_tuple(current, false);
+		}
+	}
+	result
+}
+
+/// Returns the number of keys from `lower`, at most `count`, whose tuples fit in `fuel`, and their tuples.
+///
+/// At least one key is taken, even if its tuples alone exceed `fuel`.
+pub fn fueled_step<T: TrieStorage>(trie: &T, lower: usize, count: usize, fuel: usize) -> (usize, usize) {
+	let tuples = trie.tuples_in(lower, lower + count);
+	if tuples <= fuel || count == 1 {
+		return (count, tuples);
+	}
+	// bisect for the longest step that fits, where `fits` fits (or is one) and `fails` does not.
+	let (mut fits, mut fails) = (1, count);
+	while fits + 1 < fails {
+		let middle = (fits + fails) / 2;
+		if trie.tuples_in(lower, lower + middle) <= fuel { fits = middle; } else { fails = middle; }
+	}
+	(fits, trie.tuples_in(lower, lower + fits))
+}
+
+/// Finds the ranges whose next key is the least among all ranges, for a k-way merge.
+///
+/// The positions of these ranges in `ranges` are written to `holders`, and `None` is 
+/// returned if all ranges are exhausted. If only one range holds the least key, the 
+/// result is the end of its run of keys less than the next key of every other range,
+/// and otherwise it is one past the least key.
+pub fn least_heads<T: TrieStorage>(ranges: &[(&T, usize, usize)], lowers: &[usize], holders: &mut Vec<usize>) -> Option<usize> {
+	holders.clear();
+	let mut least: Option<&T::Key> = None;
+	let mut next: Option<&T::Key> = None;
+	for (index, &(trie, _, upper)) in ranges.iter().enumerate() {
+		if lowers[index] < upper {
+			let key = trie.key(lowers[index]);
+			match least.map(|x| key.cmp(x)) {
+				None | Some(::std::cmp::Ordering::Less) => {
+					next = least;
+					least = Some(key);
+					holders.clear();
+					holders.push(index);
+				},
+				Some(::std::cmp::Ordering::Equal) => { holders.push(index); },
+				Some(::std::cmp::Ordering::Greater) => {
+					if next.map(|x| key < x).unwrap_or(true) { next = Some(key); }
+				},
+			}
+		}
+	}
+	if holders.len() == 1 {
+		let (trie, _, upper) = ranges[holders[0]];
+		let lower = lowers[holders[0]];
+		Some(match next {
+			Some(key) => trie.lower_bound(key, lower + 1, upper),
+			None => upper,
+		})
+	}
+	else {
+		least.map(|_| lowers[holders[0]] + 1)
+	}
+}
+
+/// A violation of a trie's invariants, at a layer `depth` and key `index` in that layer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Violation {
+	/// The key is not greater than the preceding key in the range of its parent key.
+	OutOfOrder { depth: usize, index: usize },
+	/// The key's offset is less than the preceding offset, or exceeds the layer beneath.
+	/// An `index` equal to the number of keys indicates that the last offset ends short.
+	Offset { depth: usize, index: usize },
+	/// The key has an empty range of values.
+	EmptyRange { depth: usize, index: usize },
+	/// The leaf has a zero difference.
+	ZeroDiff { depth: usize, index: usize },
+}
+
+impl ::std::fmt::Display for Violation {
+	fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
+		match *self {
+			Violation::OutOfOrder { depth, index } => write!(f, "key {} at depth {} is out of order", index, depth),
+			Violation::Offset { depth, index } => write!(f, "offset {} at depth {} is invalid", index, depth),
+			Violation::EmptyRange { depth, index } => write!(f, "key {} at depth {} has no values", index, depth),
+			Violation::ZeroDiff { depth, index } => write!(f, "key {} at depth {} has a zero difference", index, depth),
+		}
+	}
+}
+
+impl ::std::error::Error for Violation { }
+
+/// Checks that keys strictly increase within each of the ranges ending at `ends`.
+pub fn validate_order<T, F: Fn(&T)->&K, K: Ord>(keys: &[T], key: F, ends: &[usize], depth: usize) -> Result<(), Violation> {
+	let mut lower = 0;
+	for &upper in ends {
+		for index in (lower + 1) .. upper {
+			if key(&keys[index - 1]) >= key(&keys[index]) {
+				return Err(Violation::OutOfOrder { depth: depth, index: index });
+			}
+		}
+		lower = upper;
+	}
+	Ok(())
+}
+
+/// Checks that the offsets produced by `offsets` increase and end at `limit`.
+///
+/// Returns the offsets, which describe the ranges of keys in the layer beneath.
+pub fn validate_offsets<I: Iterator<Item=usize>>(offsets: I, limit: usize, depth: usize) -> Result<Vec<usize>, Violation> {
+	let mut ends = Vec::new();
+	let mut lower = 0;
+	for (index, upper) in offsets.enumerate() {
+		if upper < lower || upper > limit {
+			return Err(Violation::Offset { depth: depth, index: index });
+		}
+		if upper == lower {
+			return Err(Violation::EmptyRange { depth: depth, index: index });
+		}
+		ends.push(upper);
+		lower = upper;
+	}
+	if lower != limit {
+		return Err(Violation::Offset { depth: depth, index: ends.len() });
+	}
+	Ok(ends)
+}
+
 /// A layer of a trie wrapped around another trie.
 ///
 /// A `TrieLayer` contains a list of `(K, usize)` elements indicating key values
@@ -84,8 +388,41 @@ pub struct TrieLayer<K:Ord, L> {
 	pub vals: L,
 }
 
+impl<K:Ord+Clone, L: TrieStorage> TrieLayer<K, L> {
+	/// Merges several tries as `extend_merge_many`, and reports the keys it drops.
+	///
+	/// Each key present in several tries whose values cancel entirely, and which is so
+	/// absent from the result, is pushed onto `dropped` in order. Every other key lands in
+	/// `self` in order, so its position is found by enumerating the keys of the result.
+	pub fn extend_merge_many_dropping(&mut self, others: &[(&Self, usize, usize)], dropped: &mut Vec<K>) {
+		let mut lowers: Vec<usize> = others.iter().map(|x| x.1).collect();
+		let mut holders = Vec::with_capacity(others.len());
+		let mut heads = Vec::with_capacity(others.len());
+		while let Some(upper) = least_heads(others, &lowers[..], &mut holders) {
+			if holders.len() == 1 {
+				let index = holders[0];
+				self.extend_trie(others[index].0, lowers[index], upper);
+				lowers[index] = upper;
+			}
+			else {
+				heads.clear();
+				heads.extend(holders.iter().map(|&index| (others[index].0, lowers[index])));
+				let keys = self.keys.len();
+				self.extend_merge_equal(&heads[..]);
+				if self.keys.len() == keys {
+					dropped.push(heads[0].0.keys[heads[0].1].0.clone());
+				}
+				for &index in &holders {
+					lowers[index] += 1;
+				}
+			}
+		}
+	}
+}
+
 impl<K:Ord+Clone, L: TrieStorage> TrieStorage for TrieLayer<K, L> {
 	type Item = (K, L::Item);
+	type Key = K;
 	fn new() -> Self { TrieLayer { keys: vec![], vals: L::new() }}
 	fn with_capacity(other1: &Self, other2: &Self) -> Self {
 		TrieLayer { 
@@ -95,6 +432,10 @@ impl<K:Ord+Clone, L: TrieStorage> TrieStorage for TrieLayer<K, L> {
 	}
 	fn keys(&self) -> usize { self.keys.len() }
 	fn tuples(&self) -> usize { self.vals.tuples() }
+	fn tuples_in(&self, lower: usize, upper: usize) -> usize {
+		if lower < upper { self.vals.tuples_in(if lower == 0 { 0 } else { self.keys[lower-1].1 }, self.keys[upper-1].1) } else { 0 }
+	}
+	fn key(&self, index: usize) -> &K { &self.keys[index].0 }
 	fn extend_trie(&mut self, other: &Self, lower: usize, upper: usize) {
 
 		// not sure that this is critical, but we will access upper-1.
@@ -114,33 +455,35 @@ impl<K:Ord+Clone, L: TrieStorage> TrieStorage for TrieLayer<K, L> {
 		self.keys.extend(other.keys[lower .. upper]
 							  .iter()
 							  .map(|&(ref k, c)| (k.clone(), (c + self_basis) - other_basis)));
+
 		// move all of the values over ...
 		self.vals.extend_trie(&other.vals, other_basis, other.keys[upper-1].1);
 
-		assert!(self.vals.keys() == self.keys[self.keys.len()-1].1);	
+		assert!(self.vals.keys() == self.keys[self.keys.len()-1].1);
 	}
-	fn extend_merge(&mut self, other1: (&Self, usize, usize), other2: (&Self, usize, usize)) {
-		let (trie1, mut lower1, upper1) = other1;
-		let (trie2, mut lower2, upper2) = other2;
+	fn extend_merge_fueled(&mut self, other1: (&Self, &mut usize, usize), other2: (&Self, &mut usize, usize), fuel: &mut usize) {
+		let (trie1, lower1, upper1) = other1;
+		let (trie2, lower2, upper2) = other2;
 
-		self.keys.reserve(upper1 + upper2 - lower1 - lower2);
+		self.keys.reserve(upper1 + upper2 - *lower1 - *lower2);
 
-		// while both mergees are still active
-		while lower1 < upper1 && lower2 < upper2 {
-			match (trie1.keys[lower1].0).cmp(&(trie2.keys[lower2].0)) {
+		// while both mergees are still active and we have fuel
+		while *lower1 < upper1 && *lower2 < upper2 && *fuel > 0 {
+			match (trie1.keys[*lower1].0).cmp(&(trie2.keys[*lower2].0)) {
 				::std::cmp::Ordering::Less => {
 					// determine how far we can advance lower1 until we reach/pass lower2
-					let step = 1 + advance(&trie1.keys[(1+lower1)..upper1], |x| x.0 < trie2.keys[lower2].0);
-					assert!(step > 0);
-					self.extend_trie(trie1, lower1, lower1 + step);
-					lower1 += step;
+					let step = 1 + advance(&trie1.keys[(1 + *lower1)..upper1], |x| x.0 < trie2.keys[*lower2].0);
+					let (step, tuples) = fueled_step(trie1, *lower1, step, *fuel);
+					self.extend_trie(trie1, *lower1, *lower1 + step);
+					*lower1 += step;
+					*fuel = fuel.saturating_sub(tuples);
 				}
 				::std::cmp::Ordering::Equal => {
 					// need to merge vals and then push the key if the merge pushed vals.
-					let v_lower1 = if lower1 == 0 { 0 } else { trie1.keys[lower1-1].1 };
-					let v_lower2 = if lower2 == 0 { 0 } else { trie2.keys[lower2-1].1 };
-					let v_upper1 = trie1.keys[lower1].1;
-					let v_upper2 = trie2.keys[lower2].1;
+					let v_lower1 = if *lower1 == 0 { 0 } else { trie1.keys[*lower1-1].1 };
+					let v_lower2 = if *lower2 == 0 { 0 } else { trie2.keys[*lower2-1].1 };
+					let v_upper1 = trie1.keys[*lower1].1;
+					let v_upper2 = trie2.keys[*lower2].1;
 
 					// record vals_length so we can tell if anything was pushed.
 					let v_len = self.vals.keys();
@@ -149,26 +492,37 @@ impl<K:Ord+Clone, L: TrieStorage> TrieStorage for TrieLayer<K, L> {
 						(&trie2.vals, v_lower2, v_upper2)
 					);
 					if self.vals.keys() > v_len {
-						self.keys.push((trie1.keys[lower1].0.clone(), self.vals.keys()));
+						self.keys.push((trie1.keys[*lower1].0.clone(), self.vals.keys()));
 					}
 
-					lower1 += 1;
-					lower2 += 1;
+					*fuel = fuel.saturating_sub(trie1.tuples_in(*lower1, *lower1 + 1) + trie2.tuples_in(*lower2, *lower2 + 1));
+					*lower1 += 1;
+					*lower2 += 1;
 				} 
 				::std::cmp::Ordering::Greater => {
 					// determine how far we can advance lower2 until we reach/pass lower1
-					let step = 1 + advance(&trie2.keys[(1+lower2)..upper2], |x| x.0 < trie1.keys[lower1].0);
-					assert!(step > 0);
-					self.extend_trie(trie2, lower2, lower2 + step);
-					lower2 += step;
+					let step = 1 + advance(&trie2.keys[(1 + *lower2)..upper2], |x| x.0 < trie1.keys[*lower1].0);
+					let (step, tuples) = fueled_step(trie2, *lower2, step, *fuel);
+					self.extend_trie(trie2, *lower2, *lower2 + step);
+					*lower2 += step;
+					*fuel = fuel.saturating_sub(tuples);
 				}
 			}
 		}
-
-		if lower1 < upper1 { self.extend_trie(trie1, lower1, upper1); }
-		if lower2 < upper2 { self.extend_trie(trie2, lower2, upper2); }
+	}
+	fn extend_merge_equal(&mut self, heads: &[(&Self, usize)]) {
+		let ranges: Vec<_> = heads.iter().map(|&(trie, index)| {
+			let lower = if index == 0 { 0 } else { trie.keys[index-1].1 };
+			(&trie.vals, lower, trie.keys[index].1)
+		}).collect();
+		let v_len = self.vals.keys();
+		self.vals.extend_merge_many(&ranges[..]);
+		if self.vals.keys() > v_len {
+			self.keys.push((heads[0].0.keys[heads[0].1].0.clone(), self.vals.keys()));
+		}
 	}
 	fn extend_tuple(&mut self, tuple: (K, L::Item), is_new: bool) {
+		debug_assert!(is_new || self.keys.last().map(|x| x.0 <= tuple.0).unwrap_or(true), "tuples pushed out of order");
 		// if is_new or the key is not the same as the last key, advance.
 		let is_new = if is_new || self.keys.last().map(|x| x.0 != tuple.0).unwrap_or(true) {
 			self.keys.push((tuple.0, 0));
@@ -181,6 +535,21 @@ impl<K:Ord+Clone, L: TrieStorage> TrieStorage for TrieLayer<K, L> {
 		let len = self.keys.len();
 		self.keys[len-1].1 = self.vals.keys();
 	}
+	fn validate_layer(&self, ends: &[usize], depth: usize) -> Result<(), Violation> {
+		validate_order(&self.keys[..], |x| &x.0, ends, depth)?;
+		let ends = validate_offsets(self.keys.iter().map(|x| x.1), self.vals.keys(), depth)?;
+		self.vals.validate_layer(&ends[..], depth + 1)
+	}
+	fn cmp_items(item1: &Self::Item, item2: &Self::Item) -> ::std::cmp::Ordering {
+		item1.0.cmp(&item2.0).then_with(|| L::cmp_items(&item1.1, &item2.1))
+	}
+	fn add_items(item1: &mut Self::Item, item2: Self::Item) {
+		L::add_items(&mut item1.1, item2.1);
+	}
+	fn is_zero_item(item: &Self::Item) -> bool {
+		L::is_zero_item(&item.1)
+	}
+	fn item_key(item: &Self::Item) -> &K { &item.0 }
 }
 
 impl<'a, K:Ord+'a, L:'a> TrieRef<'a> for TrieLayer<K,L> where L: TrieRef<'a> {
@@ -188,10 +557,16 @@ impl<'a, K:Ord+'a, L:'a> TrieRef<'a> for TrieLayer<K,L> where L: TrieRef<'a> {
 	fn keys_cnt(&self) -> usize { self.keys.len() }
 	fn cursor(&'a self, lower: usize, upper: usize) -> Self::Cursor {
 		// type annotations apparently important to keep Rust from asploding.
-		TrieCursor::<'a,K,L>::new(&self.keys[lower .. upper], &self.vals)
+		let mut cursor = TrieCursor::<'a,K,L>::new(&self.keys[.. upper], &self.vals);
+		cursor.index = lower;
+		cursor
 	}
 }
 
+/// A cursor over the keys of a `TrieLayer`.
+///
+/// The cursor holds the keys from the start of the layer, rather than from the start 
+/// of its range, so that the value range of the key at `index` can always be found.
 pub struct TrieCursor<'a, K:Ord+'a, L:'a> {
 	pub index: usize,
 	pub keys: &'a [(K, usize)],
@@ -244,6 +619,36 @@ impl<'a, K:Ord+'a, L> Cursor<'a> for TrieCursor<'a,K,L> where L: TrieRef<'a> {
 	}
 }
 
+impl<'a, K:Ord+'a, L> BackwardCursor<'a> for TrieCursor<'a,K,L> where L: TrieRef<'a> {
+	fn prev(&mut self) -> Option<(&'a Self::Key, Self::Val)> {
+		if self.index < self.keys.len() {
+			let keys = self.keys;
+			let current = keys.len() - 1;
+			self.keys = &keys[.. current];
+
+			let v_lower = if current == 0 { 0 } else { keys[current-1].1 };
+			let v_upper = keys[current].1;
+
+			Some((
+				&keys[current].0,
+				self.vals.cursor(v_lower, v_upper),
+			))
+		}
+		else {
+			None
+		}
+	}
+
+	#[inline(never)]
+	fn seek_back(&mut self, key: &Self::Key) {
+		let count = retreat(&self.keys[self.index ..], |x| &x.0 > key);
+		self.keys = &self.keys[.. self.keys.len() - count];
+	}
+	fn peek_back(&self) -> Option<&'a Self::Key> {
+		if self.index < self.keys.len() { Some(&self.keys[self.keys.len()-1].0) } else { None }
+	}
+}
+
 impl<'a, K:Ord+'a, L:'a> Clone for TrieCursor<'a,K,L> {
 	fn clone(&self) -> Self {
 		TrieCursor::<'a,K,L> {
@@ -254,129 +659,165 @@ impl<'a, K:Ord+'a, L:'a> Clone for TrieCursor<'a,K,L> {
 	}
 }
 
-/// A trie with owned data that may be pushed into. 
-impl<K:Ord+Clone> TrieStorage for Vec<(K, i32)> {
-	type Item = (K, i32);
-	fn new() -> Self { vec![] }
-	fn with_capacity(other1: &Self, other2: &Self) -> Self { 
-		Vec::with_capacity(other1.len() + other2.len()) 
-	}
-	fn keys(&self) -> usize { self.len() }
-	fn tuples(&self) -> usize { self.len() }
-	fn extend_trie(&mut self, other: &Self, lower: usize, upper: usize) {
-		debug_assert!(lower < upper);
-		self.reserve(upper - lower);
-		self.extend_from_slice(&other[lower .. upper]);
+/// An accumulable difference, stored alongside each key in the leaves of a trie.
+///
+/// Differences for the same key are added together when tries merge, and the 
+/// key is discarded if the accumulated difference is zero.
+pub trait Monoid : Clone {
+	/// Returns true if the difference is the identity, and can be discarded.
+	fn is_zero(&self) -> bool;
+	/// Adds `other` into `self`.
+	fn plus_equals(&mut self, other: &Self);
+}
 
-	// unsafe {
-	//     let position = self.len();
-	//     let slice = &other[lower .. upper];
-	//     ::std::ptr::copy_nonoverlapping(other.as_ptr(), self.as_mut_ptr().offset(position as isize), slice.len());
-	//     self.set_len(position + slice.len());
-	// }
+macro_rules! implement_monoid {
+	($($t:ty),*) => {
+		$(
+			impl Monoid for $t {
+				#[inline(always)] fn is_zero(&self) -> bool { *self == 0 }
+				#[inline(always)] fn plus_equals(&mut self, other: &Self) { *self += *other; }
+			}
+		)*
 	}
-	fn extend_merge(&mut self, other1: (&Self, usize, usize), other2: (&Self, usize, usize)) {
+}
 
-		let (vec1, mut lower1, upper1) = other1;
-		let (vec2, mut lower2, upper2) = other2;
+implement_monoid!(i8, i16, i32, i64, isize, u8, u16, u32, u64, usize);
 
-		// perhaps overly aggressive
-		self.reserve(upper1 + upper2 - lower1 - lower2);
+impl<A: Monoid, B: Monoid> Monoid for (A, B) {
+	fn is_zero(&self) -> bool { self.0.is_zero() && self.1.is_zero() }
+	fn plus_equals(&mut self, other: &Self) {
+		self.0.plus_equals(&other.0);
+		self.1.plus_equals(&other.1);
+	}
+}
 
-		while lower1 < upper1 && lower2 < upper2 {
-			match vec1[lower1].0.cmp(&vec2[lower2].0) {
-				::std::cmp::Ordering::Less => {
-					let step = 1 + advance(&vec1[(1+lower1)..upper1], |x| x.0 < vec2[lower2].0);
-					assert!(step > 0);
-					self.extend_trie(&vec1, lower1, lower1 + step);
-					lower1 += step;
-				}
-				::std::cmp::Ordering::Equal => {
-					let count = vec1[lower1].1 + vec2[lower2].1;
-					if count != 0 {
-						self.push((vec1[lower1].0.clone(), count));
-					}
-					lower1 += 1;
-					lower2 += 1;
-				}
-				::std::cmp::Ordering::Greater => {
-					let step = 1 + advance(&vec2[(1+lower2)..upper2], |x| x.0 < vec1[lower1].0);
-					assert!(step > 0);
-					self.extend_trie(&vec2, lower2, lower2 + step);
-					lower2 += step;
-				}
+/// A monoid whose elements have additive inverses, so that differences may be retracted.
+pub trait Abelian : Monoid {
+	/// Replaces the difference with its negation.
+	fn negate(&mut self);
+}
+
+macro_rules! implement_abelian {
+	($($t:ty),*) => {
+		$(
+			impl Abelian for $t {
+				#[inline(always)] fn negate(&mut self) { *self = -*self; }
 			}
-		}
+		)*
+	}
+}
+
+implement_abelian!(i8, i16, i32, i64, isize);
 
-		if lower1 < upper1 { self.extend_trie(&vec1, lower1, upper1); }
-		if lower2 < upper2 { self.extend_trie(&vec2, lower2, upper2); }
+impl<A: Abelian, B: Abelian> Abelian for (A, B) {
+	fn negate(&mut self) {
+		self.0.negate();
+		self.1.negate();
 	}
-	fn extend_tuple(&mut self, tuple: Self::Item, _is_new: bool) {
-		self.push(tuple);
+}
+
+/// Tries whose tuples may be retracted, by adding them with negated differences.
+pub trait Retract : TrieStorage {
+	/// Negates the difference of an item, so that adding both cancels them.
+	fn negate_item(item: &mut Self::Item);
+}
+
+impl<K: Ord+Clone, L: Retract> Retract for TrieLayer<K, L> {
+	fn negate_item(item: &mut Self::Item) {
+		L::negate_item(&mut item.1);
 	}
-}	
+}
 
+impl<K: Ord+Clone, R: Abelian> Retract for Vec<(K, R)> {
+	fn negate_item(item: &mut Self::Item) {
+		item.1.negate();
+	}
+}
 
 /// A trie with owned data that may be pushed into. 
-impl<K:Ord+Clone> TrieStorage for Vec<(K, isize)> {
-	type Item = (K, isize);
+impl<K:Ord+Clone, R:Monoid> TrieStorage for Vec<(K, R)> {
+	type Item = (K, R);
+	type Key = K;
 	fn new() -> Self { vec![] }
 	fn with_capacity(other1: &Self, other2: &Self) -> Self { 
 		Vec::with_capacity(other1.len() + other2.len()) 
 	}
 	fn keys(&self) -> usize { self.len() }
 	fn tuples(&self) -> usize { self.len() }
+	fn tuples_in(&self, lower: usize, upper: usize) -> usize { upper - lower }
+	fn key(&self, index: usize) -> &K { &self[index].0 }
 	fn extend_trie(&mut self, other: &Self, lower: usize, upper: usize) {
 		debug_assert!(lower < upper);
 		self.reserve(upper - lower);
 		self.extend_from_slice(&other[lower .. upper]);
-
-	// unsafe {
-	//     let position = self.len();
-	//     let slice = &other[lower .. upper];
-	//     ::std::ptr::copy_nonoverlapping(other.as_ptr(), self.as_mut_ptr().offset(position as isize), slice.len());
-	//     self.set_len(position + slice.len());
-	// }
 	}
-	fn extend_merge(&mut self, other1: (&Self, usize, usize), other2: (&Self, usize, usize)) {
+	fn extend_merge_fueled(&mut self, other1: (&Self, &mut usize, usize), other2: (&Self, &mut usize, usize), fuel: &mut usize) {
 
-		let (vec1, mut lower1, upper1) = other1;
-		let (vec2, mut lower2, upper2) = other2;
+		let (vec1, lower1, upper1) = other1;
+		let (vec2, lower2, upper2) = other2;
 
 		// perhaps overly aggressive
-		self.reserve(upper1 + upper2 - lower1 - lower2);
+		self.reserve(upper1 + upper2 - *lower1 - *lower2);
 
-		while lower1 < upper1 && lower2 < upper2 {
-			match vec1[lower1].0.cmp(&vec2[lower2].0) {
+		while *lower1 < upper1 && *lower2 < upper2 && *fuel > 0 {
+			match vec1[*lower1].0.cmp(&vec2[*lower2].0) {
 				::std::cmp::Ordering::Less => {
-					let step = advance(&vec1[lower1..upper1], |x| x.0 < vec2[lower2].0);
-					assert!(step > 0);
-					self.extend_trie(&vec1, lower1, lower1 + step);
-					lower1 += step;
+					let step = 1 + advance(&vec1[(1 + *lower1)..upper1], |x| x.0 < vec2[*lower2].0);
+					let step = ::std::cmp::min(step, *fuel);
+					self.extend_trie(vec1, *lower1, *lower1 + step);
+					*lower1 += step;
+					*fuel -= step;
 				}
 				::std::cmp::Ordering::Equal => {
-					let count = vec1[lower1].1 + vec2[lower2].1;
-					if count != 0 {
-						self.push((vec1[lower1].0.clone(), count));
+					let mut diff = vec1[*lower1].1.clone();
+					diff.plus_equals(&vec2[*lower2].1);
+					if !diff.is_zero() {
+						self.push((vec1[*lower1].0.clone(), diff));
 					}
-					lower1 += 1;
-					lower2 += 1;
+					*lower1 += 1;
+					*lower2 += 1;
+					*fuel = fuel.saturating_sub(2);
 				}
 				::std::cmp::Ordering::Greater => {
-					let step = advance(&vec2[lower2..upper2], |x| x.0 < vec1[lower1].0);
-					assert!(step > 0);
-					self.extend_trie(&vec2, lower2, lower2 + step);
-					lower2 += step;
+					let step = 1 + advance(&vec2[(1 + *lower2)..upper2], |x| x.0 < vec1[*lower1].0);
+					let step = ::std::cmp::min(step, *fuel);
+					self.extend_trie(vec2, *lower2, *lower2 + step);
+					*lower2 += step;
+					*fuel -= step;
 				}
 			}
 		}
-
-		if lower1 < upper1 { self.extend_trie(&vec1, lower1, upper1); }
-		if lower2 < upper2 { self.extend_trie(&vec2, lower2, upper2); }
 	}
-	fn extend_tuple(&mut self, tuple: Self::Item, _is_new: bool) {
+	fn extend_merge_equal(&mut self, heads: &[(&Self, usize)]) {
+		let mut diff = heads[0].0[heads[0].1].1.clone();
+		for &(vec, index) in &heads[1 ..] {
+			diff.plus_equals(&vec[index].1);
+		}
+		if !diff.is_zero() {
+			self.push((heads[0].0[heads[0].1].0.clone(), diff));
+		}
+	}
+	fn extend_tuple(&mut self, tuple: Self::Item, is_new: bool) {
+		debug_assert!(is_new || self.last().map(|x| x.0 < tuple.0).unwrap_or(true), "tuples pushed out of order");
 		self.push(tuple);
 	}
+	fn validate_layer(&self, ends: &[usize], depth: usize) -> Result<(), Violation> {
+		validate_order(&self[..], |x| &x.0, ends, depth)?;
+		match self.iter().position(|x| x.1.is_zero()) {
+			Some(index) => Err(Violation::ZeroDiff { depth: depth, index: index }),
+			None => Ok(()),
+		}
+	}
+	fn cmp_items(item1: &Self::Item, item2: &Self::Item) -> ::std::cmp::Ordering {
+		item1.0.cmp(&item2.0)
+	}
+	fn add_items(item1: &mut Self::Item, item2: Self::Item) {
+		item1.1.plus_equals(&item2.1);
+	}
+	fn is_zero_item(item: &Self::Item) -> bool {
+		item.1.is_zero()
+	}
+	fn item_key(item: &Self::Item) -> &K { &item.0 }
 }
 
 impl<'a, K:Ord+'a, V:'a> TrieRef<'a> for Vec<(K,V)> {
@@ -429,6 +870,29 @@ impl<'a, K:Ord+'a, V:'a> Cursor<'a> for SliceCursor<'a,K,V> {
 	}
 }
 
+impl<'a, K:Ord+'a, V:'a> BackwardCursor<'a> for SliceCursor<'a,K,V> {
+	fn prev(&mut self) -> Option<(&'a Self::Key, Self::Val)> {
+		if self.index < self.slice.len() {
+			let slice = self.slice;
+			self.slice = &slice[.. slice.len() - 1];
+			Some((&slice[slice.len()-1].0, &slice[slice.len()-1].1))
+		}
+		else {
+			None
+		}
+	}
+
+	#[inline(never)]
+	fn seek_back(&mut self, key: &Self::Key) {
+		let count = retreat(&self.slice[self.index ..], |x| &x.0 > key);
+		self.slice = &self.slice[.. self.slice.len() - count];
+	}
+
+	fn peek_back(&self) -> Option<&'a Self::Key> {
+		if self.index < self.slice.len() { Some(&self.slice[self.slice.len()-1].0) } else { None }
+	}
+}
+
 impl<'a, K:Ord+'a, V:'a> Clone for SliceCursor<'a,K,V> {
 	fn clone(&self) -> Self {
 		SliceCursor::<'a,K,V> {
@@ -471,4 +935,156 @@ pub fn advance<T, F: Fn(&T)->bool>(slice: &[T], function: F) -> usize {
 	}	
 
 	index
-}
\ No newline at end of file
+}
+
+/// Reports the number of elements at the end of the slice satisfying the predicate.
+///
+/// This is the mirror image of `advance`, and relies on the predicate staying false
+/// once it becomes false when the slice is read from its end towards its start.
+#[inline(never)]
+pub fn retreat<T, F: Fn(&T)->bool>(slice: &[T], function: F) -> usize {
+
+	let len = slice.len();
+
+	// start with no retreat
+	let mut index = 0;
+	if index < len && function(&slice[len - 1]) {
+
+		// retreat in exponentially growing steps.
+		let mut step = 1;
+		while index + step < len && function(&slice[len - 1 - (index + step)]) {
+			index += step;
+			step <<= 1;
+		}
+
+		// retreat in exponentially shrinking steps.
+		step >>= 1;
+		while step > 0 {
+			if index + step < len && function(&slice[len - 1 - (index + step)]) {
+				index += step;
+			}
+			step >>= 1;
+		}
+
+		index += 1;
+	}
+
+	index
+}
+
+#[cfg(test)]
+mod tests {
+
+	use TrieStorage;
+	use column::ColumnLayer;
+	use testing::triples;
+	use super::{TrieLayer, Violation};
+
+	type Trie = TrieLayer<u32, TrieLayer<u32, Vec<(u32, i64)>>>;
+	type Columns = ColumnLayer<u32, ColumnLayer<u32, Vec<(u32, i64)>, u32>, u32>;
+	type Tuple = (u32, (u32, (u32, i64)));
+
+	fn tuples(mut seed: u64, count: usize) -> Vec<Tuple> {
+		triples(&mut seed, count, 40, 4)
+	}
+
+	fn flatten(trie: &Trie) -> Vec<(u32, u32, u32, i64)> {
+		trie.into_iter().map(|(a, (b, (c, d)))| (*a, *b, *c, *d)).collect()
+	}
+
+	#[test]
+	fn merge_many_agrees_with_merge() {
+		for inputs in 1 .. 8 {
+			let data: Vec<_> = (0 .. inputs).map(|i| tuples(i as u64, 10 + 100 * i)).collect();
+
+			let tries: Vec<Trie> = data.iter().map(|x| Trie::from_unordered(x.clone().into_iter())).collect();
+			let many = Trie::merge_many(&tries.iter().collect::<Vec<_>>()[..]);
+			let pairwise = tries.iter().fold(Trie::new(), |merged, trie| merged.merge(trie));
+			assert!(many.validate().is_ok());
+			assert_eq!(flatten(&many), flatten(&pairwise));
+			assert_eq!(many.tuples(), pairwise.tuples());
+
+			let columns: Vec<Columns> = data.iter().map(|x| Columns::from_unordered(x.clone().into_iter())).collect();
+			let many = Columns::merge_many(&columns.iter().collect::<Vec<_>>()[..]);
+			let pairwise = columns.iter().fold(Columns::new(), |merged, trie| merged.merge(trie));
+			assert!(many.validate().is_ok());
+			assert_eq!(many.keys, pairwise.keys);
+			assert_eq!(many.vals.keys, pairwise.vals.keys);
+			assert_eq!(many.vals.vals, pairwise.vals.vals);
+		}
+	}
+
+	#[test]
+	fn fueled_merge_agrees_with_merge() {
+		let trie1 = Trie::from_unordered(tuples(1, 500).into_iter());
+		let trie2 = Trie::from_unordered(tuples(2, 300).into_iter());
+		for &fuel in &[1, 7, 100] {
+			let (mut lower1, mut lower2) = (0, 0);
+			let mut merged = Trie::new();
+			while lower1 < trie1.keys() && lower2 < trie2.keys() {
+				merged.extend_merge_fueled((&trie1, &mut lower1, trie1.keys()), (&trie2, &mut lower2, trie2.keys()), &mut fuel.clone());
+			}
+			if lower1 < trie1.keys() { merged.extend_trie(&trie1, lower1, trie1.keys()); }
+			if lower2 < trie2.keys() { merged.extend_trie(&trie2, lower2, trie2.keys()); }
+			assert!(merged.validate().is_ok());
+			assert_eq!(flatten(&merged), flatten(&trie1.merge(&trie2)));
+		}
+	}
+
+	// a valid trie, to be corrupted.
+	fn valid() -> Trie {
+		let trie = Trie::from_unordered(tuples(3, 300).into_iter());
+		assert!(trie.validate().is_ok());
+		trie
+	}
+
+	#[test]
+	fn violation_out_of_order() {
+		let mut trie = valid();
+		trie.keys[4].0 = trie.keys[3].0;
+		assert_eq!(trie.validate(), Err(Violation::OutOfOrder { depth: 0, index: 4 }));
+
+		// keys in the ranges of different parent keys may repeat, but not within one range.
+		let mut trie = valid();
+		let parent = (1 .. trie.keys.len()).find(|&index| trie.keys[index].1 - trie.keys[index - 1].1 > 1).unwrap();
+		let lower = trie.keys[parent - 1].1;
+		let (first, second) = (trie.vals.keys[lower].0, trie.vals.keys[lower + 1].0);
+		trie.vals.keys[lower].0 = second;
+		trie.vals.keys[lower + 1].0 = first;
+		assert_eq!(trie.validate(), Err(Violation::OutOfOrder { depth: 1, index: lower + 1 }));
+	}
+
+	#[test]
+	fn violation_offset() {
+		// an offset less than the one before it.
+		let mut trie = valid();
+		trie.keys[2].1 = trie.keys[1].1 - 1;
+		assert_eq!(trie.validate(), Err(Violation::Offset { depth: 0, index: 2 }));
+
+		// an offset beyond the keys of the layer beneath.
+		let mut trie = valid();
+		let last = trie.keys.len() - 1;
+		trie.keys[last].1 += 1;
+		assert_eq!(trie.validate(), Err(Violation::Offset { depth: 0, index: last }));
+
+		// offsets that end short of the keys of the layer beneath.
+		let mut trie = valid();
+		let leaves = trie.vals.vals.len();
+		trie.vals.keys.push((1000, leaves));
+		assert_eq!(trie.validate(), Err(Violation::Offset { depth: 0, index: trie.keys.len() }));
+	}
+
+	#[test]
+	fn violation_empty_range() {
+		let mut trie = valid();
+		trie.keys[2].1 = trie.keys[1].1;
+		assert_eq!(trie.validate(), Err(Violation::EmptyRange { depth: 0, index: 2 }));
+	}
+
+	#[test]
+	fn violation_zero_diff() {
+		let mut trie = valid();
+		trie.vals.vals[5].1 = 0;
+		assert_eq!(trie.validate(), Err(Violation::ZeroDiff { depth: 2, index: 5 }));
+	}
+}